@@ -1,8 +1,19 @@
+pub mod backend;
 pub mod cache;
 pub mod cli;
+pub mod credential_process;
 pub mod credentials;
 pub mod dispatcher;
 pub mod environment;
+pub mod error;
+pub mod export;
 pub mod fetcher;
+pub mod mfa;
 pub mod profile;
+pub mod region;
 pub mod request;
+pub mod saml;
+pub mod server;
+pub mod session;
+pub mod sso;
+pub mod status;
@@ -0,0 +1,456 @@
+#[cfg(unix)]
+extern crate libc;
+extern crate openssl;
+extern crate rpassword;
+
+use self::openssl::hash::MessageDigest;
+use self::openssl::pkcs5;
+use self::openssl::rand;
+use self::openssl::symm::{self, Cipher};
+
+use awsudo::error::Error;
+use std::fs;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+const ENCRYPTED_SUFFIX: &str = ".enc";
+const ENCRYPTED_MAGIC: &[u8] = b"AWSUDO1\n";
+const ENCRYPTED_SALT_LENGTH: usize = 16;
+const ENCRYPTED_NONCE_LENGTH: usize = 12;
+const ENCRYPTED_TAG_LENGTH: usize = 16;
+const ENCRYPTED_KEY_ITERATIONS: usize = 100_000;
+
+// A cache dir listable by others is only reported once per run
+#[cfg(unix)]
+static LOOSE_DIR_WARNING: std::sync::Once = std::sync::Once::new();
+
+/// Storage of the cached sessions, each one an opaque blob named after its profile.
+pub trait Backend {
+    fn read(&self, dir: &Path, name: &str) -> Result<Vec<u8>, Error>;
+    fn write(&self, dir: &Path, name: &str, contents: &[u8]) -> Result<(), Error>;
+    /// Returns whether there was anything to remove.
+    fn remove(&self, dir: &Path, name: &str) -> Result<bool, Error>;
+    fn names(&self, dir: &Path) -> Result<Vec<String>, Error>;
+}
+
+/// Kind of backend selected with --cache-backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Plaintext,
+    Encrypted,
+    Memory,
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Kind, String> {
+        match name {
+            "plaintext" => Ok(Kind::Plaintext),
+            "encrypted" => Ok(Kind::Encrypted),
+            "memory" => Ok(Kind::Memory),
+            _ => Err(format!("unknown cache backend {}", name)),
+        }
+    }
+}
+
+/// One file per profile, only readable by the current user.
+pub struct Plaintext;
+
+impl Backend for Plaintext {
+    fn read(&self, dir: &Path, name: &str) -> Result<Vec<u8>, Error> {
+        read(&dir.join(name))
+    }
+
+    fn write(&self, dir: &Path, name: &str, contents: &[u8]) -> Result<(), Error> {
+        write(dir, name, contents)
+    }
+
+    fn remove(&self, dir: &Path, name: &str) -> Result<bool, Error> {
+        remove(&dir.join(name))
+    }
+
+    fn names(&self, dir: &Path) -> Result<Vec<String>, Error> {
+        Ok(files(dir)?
+            .into_iter()
+            .filter(|file| !file.ends_with(ENCRYPTED_SUFFIX))
+            .collect())
+    }
+}
+
+/// One file per profile, encrypted with AES-256-GCM under a key derived from the passphrase,
+/// so a copy of the cache dir, e.g.: in a backup, does not leak the sessions.
+pub struct Encrypted {
+    passphrase: Vec<u8>,
+}
+
+impl Encrypted {
+    pub fn new(passphrase: Vec<u8>) -> Encrypted {
+        Encrypted { passphrase }
+    }
+
+    // Failing to derive the key is reported as the error of the caller, reading or writing
+    fn key(&self, salt: &[u8], error: Error) -> Result<[u8; 32], Error> {
+        let mut key = [0; 32];
+        pkcs5::pbkdf2_hmac(
+            &self.passphrase,
+            salt,
+            ENCRYPTED_KEY_ITERATIONS,
+            MessageDigest::sha256(),
+            &mut key,
+        )
+        .map_err(|_| error)?;
+
+        Ok(key)
+    }
+
+    // The name is authenticated along with the contents, so files cannot be swapped around
+    fn seal(&self, name: &str, contents: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut salt = [0; ENCRYPTED_SALT_LENGTH];
+        let mut nonce = [0; ENCRYPTED_NONCE_LENGTH];
+        let mut tag = [0; ENCRYPTED_TAG_LENGTH];
+        rand::rand_bytes(&mut salt).map_err(|_| Error::CacheFileNotCreated)?;
+        rand::rand_bytes(&mut nonce).map_err(|_| Error::CacheFileNotCreated)?;
+
+        let ciphertext = symm::encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key(&salt, Error::CacheFileNotCreated)?,
+            Some(&nonce),
+            name.as_bytes(),
+            contents,
+            &mut tag,
+        )
+        .map_err(|_| Error::CacheFileNotCreated)?;
+
+        Ok([ENCRYPTED_MAGIC, &salt, &nonce, &tag, &ciphertext].concat())
+    }
+
+    fn open(&self, name: &str, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+        let header = ENCRYPTED_MAGIC.len();
+        let nonce = header + ENCRYPTED_SALT_LENGTH;
+        let tag = nonce + ENCRYPTED_NONCE_LENGTH;
+        let ciphertext = tag + ENCRYPTED_TAG_LENGTH;
+        // A truncated file or another header was tampered with, not a missing session
+        if sealed.len() < ciphertext || &sealed[..header] != ENCRYPTED_MAGIC {
+            return Err(Error::CacheNotDecrypted);
+        }
+
+        symm::decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.key(&sealed[header..nonce], Error::CacheNotDecrypted)?,
+            Some(&sealed[nonce..tag]),
+            name.as_bytes(),
+            &sealed[ciphertext..],
+            &sealed[tag..ciphertext],
+        )
+        .map_err(|_| Error::CacheNotDecrypted)
+    }
+}
+
+impl Backend for Encrypted {
+    fn read(&self, dir: &Path, name: &str) -> Result<Vec<u8>, Error> {
+        self.open(name, &read(&dir.join(encrypted(name)))?)
+    }
+
+    fn write(&self, dir: &Path, name: &str, contents: &[u8]) -> Result<(), Error> {
+        write(dir, &encrypted(name), &self.seal(name, contents)?)
+    }
+
+    fn remove(&self, dir: &Path, name: &str) -> Result<bool, Error> {
+        remove(&dir.join(encrypted(name)))
+    }
+
+    fn names(&self, dir: &Path) -> Result<Vec<String>, Error> {
+        Ok(files(dir)?
+            .into_iter()
+            .filter(|file| file.ends_with(ENCRYPTED_SUFFIX))
+            .map(|file| file[..file.len() - ENCRYPTED_SUFFIX.len()].to_string())
+            .collect())
+    }
+}
+
+fn encrypted(name: &str) -> String {
+    format!("{}{}", name, ENCRYPTED_SUFFIX)
+}
+
+/// Keeps nothing, every invocation requests a new session.
+pub struct Memory;
+
+impl Backend for Memory {
+    fn read(&self, _: &Path, _: &str) -> Result<Vec<u8>, Error> {
+        Err(Error::CacheNotFound)
+    }
+
+    fn write(&self, _: &Path, _: &str, _: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn remove(&self, _: &Path, _: &str) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn names(&self, _: &Path) -> Result<Vec<String>, Error> {
+        Ok(vec![])
+    }
+}
+
+/// Reads the passphrase of the encrypted backend from the key file, ignoring the trailing
+/// newline most editors add.
+pub fn read_key_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut key = fs::read(path).map_err(|_| Error::CacheKeyNotRead)?;
+    while key.last() == Some(&b'\n') || key.last() == Some(&b'\r') {
+        key.pop();
+    }
+
+    if key.is_empty() {
+        Err(Error::CacheKeyNotRead)
+    } else {
+        Ok(key)
+    }
+}
+
+/// Prompts for the passphrase of the encrypted backend on the controlling terminal, like the
+/// MFA token.
+pub fn collect_passphrase() -> Result<Vec<u8>, Error> {
+    match rpassword::read_password_from_tty(Some("Please type your cache passphrase: ")) {
+        Ok(ref input) if !input.is_empty() => Ok(input.clone().into_bytes()),
+        _ => Err(Error::CacheKeyNotRead),
+    }
+}
+
+/// Creates the cache dir only accessible by the current user. An existing dir, e.g.: given with
+/// `--cache-dir`, is left as it is, but refused when another user owns it or may write to it.
+pub fn create_dir(dir: &Path) -> Result<(), Error> {
+    if let Ok(metadata) = fs::metadata(dir) {
+        return check_dir(dir, &metadata);
+    }
+
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+
+    builder.create(dir).map_err(|_| Error::CacheDirNotCreated)
+}
+
+#[cfg(unix)]
+fn check_dir(dir: &Path, metadata: &fs::Metadata) -> Result<(), Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    if !metadata.is_dir() {
+        return Err(Error::CacheDirNotCreated);
+    }
+    if metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o022 != 0 {
+        return Err(Error::CacheDirInsecure);
+    }
+
+    // The files are still only readable by the user, only their names are listed
+    if metadata.mode() & 0o077 != 0 {
+        LOOSE_DIR_WARNING.call_once(|| {
+            eprintln!(
+                "awsudo: the cache dir {} can be listed by others, going on with it",
+                dir.display()
+            )
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_dir(_: &Path, metadata: &fs::Metadata) -> Result<(), Error> {
+    if metadata.is_dir() {
+        Ok(())
+    } else {
+        Err(Error::CacheDirNotCreated)
+    }
+}
+
+// Writes to a temporary file only readable by the current user, moving it in place once it is
+// on disk, so the cache is never left half written
+fn write(dir: &Path, file: &str, contents: &[u8]) -> Result<(), Error> {
+    create_dir(dir)?;
+
+    let path = dir.join(file);
+    let temporary = dir.join(format!(".{}.{}.tmp", file, process::id()));
+
+    // A leftover of a crashed run is replaced, never followed
+    let _ = fs::remove_file(&temporary);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let result = options.open(&temporary).and_then(|mut f| {
+        f.write_all(contents)?;
+        f.sync_all()?;
+        fs::rename(&temporary, &path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+        return Err(Error::CacheFileNotCreated);
+    }
+
+    // The rename itself is only durable once the dir is on disk
+    if let Ok(d) = File::open(dir) {
+        let _ = d.sync_all();
+    }
+
+    Ok(())
+}
+
+// Refuses files another user could have written or may read, e.g.: on shared hosts
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path).map_err(|_| Error::CacheNotFound)?;
+    let metadata = file.metadata().map_err(|_| Error::CacheNotFound)?;
+
+    if !secure(&metadata) {
+        return Err(Error::CacheInsecure);
+    }
+
+    let mut contents = vec![];
+    file.read_to_end(&mut contents)
+        .map_err(|_| Error::CacheNotFound)?;
+
+    Ok(contents)
+}
+
+#[cfg(unix)]
+fn secure(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.uid() == unsafe { libc::geteuid() } && metadata.mode() & 0o077 == 0
+}
+
+#[cfg(not(unix))]
+fn secure(_: &fs::Metadata) -> bool {
+    true
+}
+
+fn remove(path: &Path) -> Result<bool, Error> {
+    match fs::remove_file(path) {
+        Ok(_) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(_) => Err(Error::CacheNotRemoved),
+    }
+}
+
+// Lock and temporary files are hidden
+fn files(dir: &Path) -> Result<Vec<String>, Error> {
+    match fs::read_dir(dir) {
+        Ok(read) => Ok(read
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| !name.starts_with('.'))
+            .collect()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(_) => Err(Error::CacheNotFound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::backend::{Backend, Encrypted, Kind, Memory, Plaintext};
+    use awsudo::error::Error;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn fixtures_tmp_path(name: &str) -> PathBuf {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures/tmp");
+        p.push(name);
+        p
+    }
+
+    #[test]
+    fn it_parses_the_backend_kind() {
+        assert_eq!("plaintext".parse::<Kind>(), Ok(Kind::Plaintext));
+        assert_eq!("encrypted".parse::<Kind>(), Ok(Kind::Encrypted));
+        assert_eq!("memory".parse::<Kind>(), Ok(Kind::Memory));
+        assert!("keychain".parse::<Kind>().is_err());
+    }
+
+    #[test]
+    fn it_encrypts_the_cached_session() {
+        let dir = fixtures_tmp_path("backend_encrypted");
+        let backend = Encrypted::new(b"correct horse".to_vec());
+
+        assert_eq!(backend.write(&dir, "prod", b"SESSION_TOKEN=B03"), Ok(()));
+
+        let sealed = fs::read(dir.join("prod.enc")).unwrap();
+        assert!(!sealed.windows(b"B03".len()).any(|window| window == b"B03"));
+        assert_eq!(
+            backend.read(&dir, "prod"),
+            Ok(b"SESSION_TOKEN=B03".to_vec())
+        );
+        assert_eq!(backend.names(&dir), Ok(vec!["prod".to_string()]));
+        assert_eq!(Plaintext.names(&dir), Ok(vec![]));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_refuses_a_wrong_passphrase_or_tampered_file() {
+        let dir = fixtures_tmp_path("backend_tampered");
+        Encrypted::new(b"correct horse".to_vec())
+            .write(&dir, "prod", b"SESSION_TOKEN=B03")
+            .unwrap();
+
+        assert_eq!(
+            Encrypted::new(b"battery staple".to_vec()).read(&dir, "prod"),
+            Err(Error::CacheNotDecrypted)
+        );
+
+        let mut sealed = fs::read(dir.join("prod.enc")).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        fs::write(dir.join("prod.enc"), &sealed).unwrap();
+        assert_eq!(
+            Encrypted::new(b"correct horse".to_vec()).read(&dir, "prod"),
+            Err(Error::CacheNotDecrypted)
+        );
+
+        // Another profile's file is not accepted in place of this one
+        fs::rename(dir.join("prod.enc"), dir.join("audit.enc")).unwrap();
+        assert_eq!(
+            Encrypted::new(b"correct horse".to_vec()).read(&dir, "audit"),
+            Err(Error::CacheNotDecrypted)
+        );
+
+        fs::write(dir.join("audit.enc"), &sealed[..10]).unwrap();
+        assert_eq!(
+            Encrypted::new(b"correct horse".to_vec()).read(&dir, "audit"),
+            Err(Error::CacheNotDecrypted)
+        );
+
+        sealed[0] ^= 1;
+        fs::write(dir.join("audit.enc"), &sealed).unwrap();
+        assert_eq!(
+            Encrypted::new(b"correct horse".to_vec()).read(&dir, "audit"),
+            Err(Error::CacheNotDecrypted)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_keeps_nothing_in_memory() {
+        let dir = fixtures_tmp_path("backend_memory");
+
+        assert_eq!(Memory.write(&dir, "prod", b"SESSION_TOKEN=B03"), Ok(()));
+        assert_eq!(Memory.read(&dir, "prod"), Err(Error::CacheNotFound));
+        assert_eq!(Memory.names(&dir), Ok(vec![]));
+        assert!(!dir.exists());
+    }
+}
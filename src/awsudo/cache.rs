@@ -1,17 +1,51 @@
 extern crate chrono;
+extern crate hex;
 extern crate ini;
+#[cfg(unix)]
+extern crate libc;
+extern crate rusoto_core;
+extern crate sha2;
 
 use self::chrono::{DateTime, Duration, Utc};
 use self::ini::Ini;
+use self::rusoto_core::Region;
+use self::sha2::{Digest, Sha256};
 
+use awsudo::backend;
+use awsudo::backend::{Backend, Plaintext};
 use awsudo::credentials::Credentials;
+use awsudo::error::Error;
 use awsudo::fetcher::Fetcher;
-use std::fs;
+use awsudo::profile::{Profile, StaticCredentials};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
 use std::path::PathBuf;
+use std::thread;
+use std::time;
+
+const CACHE_DEFAULT_EXPIRY_WINDOW_SECONDS: i64 = 300;
+const CACHE_LOCK_RETRY_MILLISECONDS: u64 = 100;
+const CACHE_MFA_SESSION_PREFIX: &str = "mfa-";
+
+// Long enough for the process holding the lock to collect the MFA token
+pub const CACHE_LOCK_TIMEOUT_SECONDS: u64 = 120;
 
 pub struct Cache {
     pub dir: PathBuf,
     pub file: String,
+    pub expiry_window: Duration,
+    pub role_arn: Option<String>,
+    pub fingerprint: Option<String>,
+    pub backend: Box<dyn Backend>,
+}
+
+/// Summary of a cached session, without its secrets.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub profile: String,
+    pub role_arn: Option<String>,
+    pub expires_at: DateTime<Utc>,
 }
 
 impl Cache {
@@ -19,46 +53,333 @@ impl Cache {
         Cache {
             dir,
             file: filename.to_owned(),
+            expiry_window: Duration::seconds(CACHE_DEFAULT_EXPIRY_WINDOW_SECONDS),
+            role_arn: None,
+            fingerprint: None,
+            backend: Box::new(Plaintext),
         }
     }
+
+    /// Cached credentials are considered expired this long before their real expiration,
+    /// so the command has time to use them.
+    pub fn with_expiry_window(self, seconds: i64) -> Cache {
+        Cache {
+            expiry_window: Duration::seconds(seconds),
+            ..self
+        }
+    }
+
+    /// Role the cached credentials belong to, only kept to describe the entry.
+    pub fn with_role_arn(self, role_arn: Option<String>) -> Cache {
+        Cache { role_arn, ..self }
+    }
+
+    /// Cached credentials are only returned if they were requested for the same settings,
+    /// as identified by `fingerprint`.
+    pub fn with_fingerprint(self, fingerprint: String) -> Cache {
+        Cache {
+            fingerprint: Some(fingerprint),
+            ..self
+        }
+    }
+
+    /// Where the sessions are stored, plaintext files by default.
+    pub fn with_backend(self, backend: Box<dyn Backend>) -> Cache {
+        Cache { backend, ..self }
+    }
+}
+
+/// Held while refreshing the cached session of a profile, so parallel invocations wait for it
+/// instead of prompting for MFA again. Released when dropped, or when the process dies.
+pub struct Lock {
+    _file: File,
 }
 
 impl Cache {
-    pub fn persist(&self, credentials: Credentials) -> Result<(), &'static str> {
-        if credentials.cached {
-            Ok(())
-        } else {
-            match fs::create_dir_all(&self.dir) {
-                Ok(_) => {
-                    let path = self.dir.join(&self.file);
-                    //TODO move this logic to credentials (read from STS request)
-                    let expires_at = (Utc::now() + Duration::hours(1)).to_rfc3339();
-
-                    let mut conf = Ini::new();
-                    conf.with_section(None::<String>)
-                        .set("ACCESS_KEY_ID", credentials.access_key_id)
-                        .set("SECRET_ACCESS_KEY", credentials.secret_access_key)
-                        .set("SESSION_TOKEN", credentials.session_token)
-                        .set("SESSION_EXPIRES_AT", expires_at);
-
-                    match conf.write_to_file(path) {
-                        Ok(_) => Ok(()),
-                        Err(_) => Err("Failed to persist cache: file cannot be created"),
-                    }
+    /// Waits for the lock of the profile up to the timeout, a process holding it for longer is
+    /// assumed to be stuck.
+    pub fn lock(&self, timeout: time::Duration) -> Result<Lock, Error> {
+        backend::create_dir(&self.dir)?;
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options
+            .open(self.dir.join(format!(".{}.lock", self.file)))
+            .map_err(|_| Error::CacheFileNotCreated)?;
+
+        let started = time::Instant::now();
+        loop {
+            match try_lock(&file) {
+                Ok(true) => return Ok(Lock { _file: file }),
+                Ok(false) if started.elapsed() < timeout => {
+                    thread::sleep(time::Duration::from_millis(CACHE_LOCK_RETRY_MILLISECONDS))
+                }
+                Ok(false) => return Err(Error::CacheLockTimeout),
+                Err(_) => return Err(Error::CacheLockUnsupported),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+
+    match io::Error::last_os_error() {
+        ref e if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        e => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn try_lock(_: &File) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// Identifies the settings the credentials are requested with: every role of the chain with
+/// its MFA device, session settings, static keys or credential process, web identity, SAML
+/// assertion source and SSO account role, and the STS endpoint.
+pub fn fingerprint(profiles: &[Profile], region: &Region) -> String {
+    let mut hasher = Sha256::default();
+
+    for p in profiles {
+        hasher.input(
+            format!(
+                "{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}|{:?}\n",
+                p.role_arn,
+                p.mfa_serial,
+                p.external_id,
+                p.duration_seconds,
+                p.role_session_name,
+                p.static_credentials.as_ref().map(|c| &c.access_key_id),
+                p.web_identity_token_file,
+                p.saml_assertion,
+                p.saml_principal_arn,
+                p.sso_session.as_ref().map(|s| &s.start_url),
+                p.sso_account_id,
+                p.sso_role_name,
+                p.credential_process,
+            )
+            .as_bytes(),
+        );
+    }
+    hasher.input(format!("{:?}", region).as_bytes());
+
+    hex::encode(hasher.result())
+}
+
+/// Name of the cached session of an MFA device, its serial being an ARN.
+pub fn mfa_session_name(serial: &str) -> String {
+    let name: String = serial
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!("{}{}", CACHE_MFA_SESSION_PREFIX, name)
+}
+
+/// Identifies the settings the session of an MFA device is requested with: the device, the
+/// static keys it belongs to, if any, and the STS endpoint.
+pub fn mfa_fingerprint(
+    serial: &str,
+    static_credentials: Option<&StaticCredentials>,
+    region: &Region,
+) -> String {
+    let mut hasher = Sha256::default();
+    hasher.input(
+        format!(
+            "{}|{:?}|{:?}",
+            serial,
+            static_credentials.map(|c| &c.access_key_id),
+            region
+        )
+        .as_bytes(),
+    );
+
+    hex::encode(hasher.result())
+}
+
+impl Cache {
+    pub fn persist(&self, credentials: &Credentials) -> Result<(), Error> {
+        if credentials.cached || !credentials.cacheable {
+            return Ok(());
+        }
+
+        let mut conf = Ini::new();
+        conf.with_section(None::<String>)
+            .set("ACCESS_KEY_ID", credentials.access_key_id.as_str())
+            .set("SECRET_ACCESS_KEY", credentials.secret_access_key.as_str())
+            .set("SESSION_TOKEN", credentials.session_token.as_str())
+            .set("SESSION_EXPIRES_AT", credentials.expires_at.to_rfc3339());
+        conf.with_section(None::<String>)
+            .set("PROFILE", self.file.as_str());
+        if let Some(ref role_arn) = self.role_arn {
+            conf.with_section(None::<String>)
+                .set("ROLE_ARN", role_arn.as_str());
+        }
+        if let Some(ref fingerprint) = self.fingerprint {
+            conf.with_section(None::<String>)
+                .set("FINGERPRINT", fingerprint.as_str());
+        }
+
+        let mut contents = vec![];
+        conf.write_to(&mut contents)
+            .map_err(|_| Error::CacheFileNotCreated)?;
+
+        self.backend.write(&self.dir, &self.file, &contents)
+    }
+
+    fn load(&self, file: &str) -> Result<Ini, Error> {
+        let contents = self.backend.read(&self.dir, file)?;
+
+        match String::from_utf8(contents).map(|c| Ini::load_from_str(&c)) {
+            Ok(Ok(ini_file)) => Ok(ini_file),
+            _ => Err(Error::CacheNotFound),
+        }
+    }
+}
+
+impl Cache {
+    /// Describes the cached session of the profile, even if it is already expired.
+    pub fn entry(&self) -> Result<Entry, Error> {
+        self.entry_of(&self.file)
+    }
+
+    /// Removes the cached session of the profile, returning whether there was one.
+    pub fn clear(&self) -> Result<bool, Error> {
+        self.backend.remove(&self.dir, &self.file)
+    }
+
+    /// Describes every cached session in the dir, ordered by profile. Sessions that cannot be
+    /// decrypted or are not secure are reported and skipped.
+    pub fn list(&self) -> Result<Vec<Entry>, Error> {
+        let mut entries = self.entries()?;
+        entries.sort_by(|a, b| a.profile.cmp(&b.profile));
+
+        Ok(entries)
+    }
+
+    /// Removes every cached session in the dir, returning the removed profiles.
+    pub fn clear_all(&self) -> Result<Vec<String>, Error> {
+        self.remove(|_| true)
+    }
+
+    /// Removes the expired cached sessions in the dir, returning their profiles. Other files
+    /// are left alone, the dir may be shared. Sessions that cannot be decrypted or are not
+    /// secure, e.g.: because of a mistyped passphrase, are reported and kept.
+    pub fn gc(&self) -> Result<Vec<String>, Error> {
+        let now = Utc::now();
+        let garbage = self
+            .entries()?
+            .into_iter()
+            .filter(|entry| entry.expires_at <= now)
+            .map(|entry| entry.profile)
+            .collect::<Vec<_>>();
+
+        self.remove(|file| garbage.iter().any(|g| g == file))
+    }
+
+    // The sessions of the dir, skipping the files that are not sessions
+    fn entries(&self) -> Result<Vec<Entry>, Error> {
+        let mut entries = vec![];
+        for file in self.backend.names(&self.dir)? {
+            match self.entry_of(&file) {
+                Ok(entry) => entries.push(entry),
+                Err(ref e @ Error::CacheNotDecrypted) | Err(ref e @ Error::CacheInsecure) => {
+                    eprintln!("awsudo: {}: {}, skipping it", file, e)
                 }
-                Err(_) => Err("Failed to persist cache: dir cannot be created"),
+                Err(_) => (),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn remove<F: Fn(&str) -> bool>(&self, condition: F) -> Result<Vec<String>, Error> {
+        let mut removed = vec![];
+        for file in self.backend.names(&self.dir)? {
+            if condition(&file) && self.backend.remove(&self.dir, &file)? {
+                removed.push(file);
             }
         }
+        removed.sort();
+
+        Ok(removed)
+    }
+
+    fn entry_of(&self, file: &str) -> Result<Entry, Error> {
+        let ini_file = self.load(file)?;
+        let section = ini_file.general_section();
+        let complete = ["ACCESS_KEY_ID", "SECRET_ACCESS_KEY", "SESSION_TOKEN"]
+            .iter()
+            .all(|key| section.contains_key(*key));
+
+        match section.get("SESSION_EXPIRES_AT") {
+            Some(_) if !complete => Err(Error::CacheMissingValues),
+            Some(raw) => match raw.parse::<DateTime<Utc>>() {
+                Ok(expires_at) => Ok(Entry {
+                    profile: file.to_owned(),
+                    role_arn: section.get("ROLE_ARN").map(|s| s.to_string()),
+                    expires_at,
+                }),
+                Err(_) => Err(Error::CacheInvalidDate),
+            },
+            None => Err(Error::CacheMissingValues),
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let remaining = self.expires_at - Utc::now();
+        let lifetime = if remaining > Duration::zero() {
+            format!(
+                "expires in {}h{:02}m",
+                remaining.num_hours(),
+                remaining.num_minutes() % 60
+            )
+        } else {
+            "expired".to_string()
+        };
+
+        write!(
+            f,
+            "{}\t{}\t{}",
+            self.profile,
+            self.role_arn.as_ref().map_or("-", |r| r.as_str()),
+            lifetime
+        )
     }
 }
 
 impl Fetcher for Cache {
-    fn fetch(&self) -> Result<Credentials, &'static str> {
-        match Ini::load_from_file(self.dir.join(&self.file)) {
-            Err(_) => Err("Cache file is not present or not valid"),
+    fn fetch(&self) -> Result<Credentials, Error> {
+        match self.load(&self.file) {
+            Err(e) => Err(e),
             Ok(ini_file) => {
                 let section = ini_file.general_section();
 
+                if let Some(ref fingerprint) = self.fingerprint {
+                    if section.get("FINGERPRINT") != Some(fingerprint) {
+                        return Err(Error::CacheMismatch);
+                    }
+                }
+
                 match (
                     section.get(&"ACCESS_KEY_ID".to_string()),
                     section.get(&"SECRET_ACCESS_KEY".to_string()),
@@ -72,20 +393,22 @@ impl Fetcher for Cache {
                         Some(session_expires_at_raw),
                     ) => match session_expires_at_raw.parse::<DateTime<Utc>>() {
                         Ok(session_expires_at) => {
-                            if session_expires_at > Utc::now() {
+                            if session_expires_at - self.expiry_window > Utc::now() {
                                 Ok(Credentials {
                                     access_key_id: access_key_id.clone(),
                                     secret_access_key: secret_access_key.clone(),
                                     session_token: session_token.clone(),
+                                    expires_at: session_expires_at,
                                     cached: true,
+                                    cacheable: true,
                                 })
                             } else {
-                                Err("Cache file is expired")
+                                Err(Error::CacheExpired)
                             }
                         }
-                        Err(_) => Err("Cache file does not have a valid date"),
+                        Err(_) => Err(Error::CacheInvalidDate),
                     },
-                    (_, _, _, _) => Err("Cache file is missing required values"),
+                    (_, _, _, _) => Err(Error::CacheMissingValues),
                 }
             }
         }
@@ -94,10 +417,20 @@ impl Fetcher for Cache {
 
 #[cfg(test)]
 mod tests {
-    use awsudo::cache::Cache;
+    use awsudo::backend::{Encrypted, Memory};
+    use awsudo::cache;
+    use awsudo::cache::chrono::{DateTime, Duration, Utc};
+    use awsudo::cache::rusoto_core::Region;
+    use awsudo::cache::{Cache, Entry};
     use awsudo::credentials::Credentials;
+    use awsudo::error::Error;
     use awsudo::fetcher::Fetcher;
+    use awsudo::profile::Profile;
     use std::fs;
+    #[cfg(unix)]
+    use std::fs::Permissions;
+    #[cfg(unix)]
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
     use std::path::{Path, PathBuf};
 
     fn fixtures_tmp_path() -> PathBuf {
@@ -112,55 +445,69 @@ mod tests {
         p
     }
 
+    // git does not keep the permissions, cache files readable by others are refused
+    #[cfg(unix)]
+    fn secure(path: &Path) {
+        fs::set_permissions(path, Permissions::from_mode(0o600)).unwrap();
+    }
+
+    #[cfg(not(unix))]
+    fn secure(_: &Path) {}
+
+    // Fetches a copy of the fixture, leaving the tracked file untouched
+    fn fetch_fixture(profile: &str) -> Result<Credentials, Error> {
+        let dir = fixtures_tmp_path().with_file_name(format!("cache_fixture_{}", profile));
+        fs::create_dir_all(&dir).unwrap();
+        if fixtures_path().join(profile).exists() {
+            fs::copy(fixtures_path().join(profile), dir.join(profile)).unwrap();
+            secure(&dir.join(profile));
+        }
+
+        let result = Cache::new(dir.clone(), profile).fetch();
+        fs::remove_dir_all(dir).unwrap();
+
+        result
+    }
+
     #[test]
     fn it_returns_error_when_the_file_is_not_present() {
-        assert_eq!(
-            Cache::new(fixtures_path(), "path").fetch(),
-            Err("Cache file is not present or not valid")
-        );
+        assert_eq!(fetch_fixture("path"), Err(Error::CacheNotFound));
     }
 
     #[test]
     fn it_returns_error_when_the_file_is_not_ini_valid() {
-        assert_eq!(
-            Cache::new(fixtures_path(), "invalid").fetch(),
-            Err("Cache file is missing required values")
-        );
+        assert_eq!(fetch_fixture("invalid"), Err(Error::CacheMissingValues));
     }
 
     #[test]
     fn it_returns_error_when_the_file_is_missing_values_valid() {
         assert_eq!(
-            Cache::new(fixtures_path(), "invalid_missing_values").fetch(),
-            Err("Cache file is missing required values")
+            fetch_fixture("invalid_missing_values"),
+            Err(Error::CacheMissingValues)
         );
     }
 
     #[test]
     fn it_returns_error_when_the_file_date_is_not_valid() {
-        assert_eq!(
-            Cache::new(fixtures_path(), "invalid_date").fetch(),
-            Err("Cache file does not have a valid date")
-        );
+        assert_eq!(fetch_fixture("invalid_date"), Err(Error::CacheInvalidDate));
     }
 
     #[test]
     fn it_returns_error_when_the_file_date_is_expired() {
-        assert_eq!(
-            Cache::new(fixtures_path(), "invalid_expired").fetch(),
-            Err("Cache file is expired")
-        );
+        assert_eq!(fetch_fixture("invalid_expired"), Err(Error::CacheExpired));
     }
 
     #[test]
     fn it_returns_the_credentails_when_the_valid() {
         assert_eq!(
-            Cache::new(fixtures_path(), "valid").fetch(),
+            fetch_fixture("valid"),
             Ok(Credentials {
                 access_key_id: "ASIA3NOTVALID2WN5".to_string(),
                 secret_access_key: "8s7k+21mKladUU9d".to_string(),
                 session_token: "AgoGb3JpZ2luECwaDGV1LW".to_string(),
+                expires_at: "5019-04-01T17:24:09Z".parse::<DateTime<Utc>>().unwrap(),
                 cached: true,
+                cacheable: true,
             }),
         );
     }
@@ -171,11 +518,13 @@ mod tests {
             access_key_id: "-".to_string(),
             secret_access_key: "-".to_string(),
             session_token: "-".to_string(),
+            expires_at: Utc::now(),
             cached: true,
+            cacheable: true,
         };
 
         assert_eq!(
-            Cache::new(fixtures_tmp_path(), "it-doesnt-matter").persist(cr),
+            Cache::new(fixtures_tmp_path(), "it-doesnt-matter").persist(&cr),
             Ok(()),
         );
     }
@@ -186,12 +535,14 @@ mod tests {
             access_key_id: "-".to_string(),
             secret_access_key: "-".to_string(),
             session_token: "-".to_string(),
+            expires_at: Utc::now(),
             cached: false,
+            cacheable: true,
         };
 
         assert_eq!(
-            Cache::new(PathBuf::from("\0"), "it-doesnt-matter").persist(cr),
-            Err("Failed to persist cache: dir cannot be created"),
+            Cache::new(PathBuf::from("\0"), "it-doesnt-matter").persist(&cr),
+            Err(Error::CacheDirNotCreated),
         );
     }
 
@@ -201,13 +552,396 @@ mod tests {
             access_key_id: "A23".to_string(),
             secret_access_key: "M07".to_string(),
             session_token: "B03".to_string(),
+            expires_at: Utc::now() + Duration::hours(1),
             cached: false,
+            cacheable: true,
         };
 
-        assert_eq!(Cache::new(fixtures_tmp_path(), "file").persist(cr), Ok(()),);
+        assert_eq!(Cache::new(fixtures_tmp_path(), "file").persist(&cr), Ok(()),);
 
         assert_eq!(Path::new(&fixtures_tmp_path()).join("file").exists(), true);
 
         fs::remove_dir_all(fixtures_tmp_path()).unwrap();
     }
+
+    #[test]
+    fn it_persists_the_exact_expiration_of_the_credentials() {
+        let dir = fixtures_tmp_path().with_file_name("cache_exact");
+        let expires_at = "2031-02-03T04:05:06Z".parse::<DateTime<Utc>>().unwrap();
+        let cr = Credentials {
+            access_key_id: "A23".to_string(),
+            secret_access_key: "M07".to_string(),
+            session_token: "B03".to_string(),
+            expires_at,
+            cached: false,
+            cacheable: true,
+        };
+
+        assert_eq!(Cache::new(dir.clone(), "file").persist(&cr), Ok(()));
+
+        assert_eq!(
+            Cache::new(dir.clone(), "file")
+                .fetch()
+                .map(|c| c.expires_at),
+            Ok(expires_at)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_returns_error_when_the_credentials_expire_within_the_expiry_window() {
+        let dir = fixtures_tmp_path().with_file_name("cache_window");
+        let cr = Credentials {
+            access_key_id: "A23".to_string(),
+            secret_access_key: "M07".to_string(),
+            session_token: "B03".to_string(),
+            expires_at: Utc::now() + Duration::minutes(10),
+            cached: false,
+            cacheable: true,
+        };
+
+        assert_eq!(Cache::new(dir.clone(), "file").persist(&cr), Ok(()));
+
+        assert_eq!(
+            Cache::new(dir.clone(), "file")
+                .with_expiry_window(900)
+                .fetch(),
+            Err(Error::CacheExpired)
+        );
+        assert!(Cache::new(dir.clone(), "file")
+            .with_expiry_window(60)
+            .fetch()
+            .is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn persisted(dir: &Path, profile: &str, expires_at: DateTime<Utc>) {
+        let cr = Credentials {
+            access_key_id: "A23".to_string(),
+            secret_access_key: "M07".to_string(),
+            session_token: "B03".to_string(),
+            expires_at,
+            cached: false,
+            cacheable: true,
+        };
+
+        Cache::new(dir.to_path_buf(), profile)
+            .with_role_arn(Some(format!("arn:aws:iam::1:role/{}", profile)))
+            .persist(&cr)
+            .unwrap();
+    }
+
+    #[test]
+    fn it_lists_the_cached_sessions() {
+        let dir = fixtures_tmp_path().with_file_name("cache_list");
+        let expires_at = "2031-02-03T04:05:06Z".parse::<DateTime<Utc>>().unwrap();
+        persisted(&dir, "prod", expires_at);
+        persisted(&dir, "audit", expires_at);
+
+        assert_eq!(
+            Cache::new(dir.clone(), "prod").list(),
+            Ok(vec![
+                Entry {
+                    profile: "audit".to_string(),
+                    role_arn: Some("arn:aws:iam::1:role/audit".to_string()),
+                    expires_at,
+                },
+                Entry {
+                    profile: "prod".to_string(),
+                    role_arn: Some("arn:aws:iam::1:role/prod".to_string()),
+                    expires_at,
+                },
+            ])
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_lists_nothing_when_the_dir_does_not_exist() {
+        assert_eq!(
+            Cache::new(fixtures_tmp_path().with_file_name("cache_none"), "prod").list(),
+            Ok(vec![])
+        );
+    }
+
+    #[test]
+    fn it_clears_the_cached_session_of_a_profile() {
+        let dir = fixtures_tmp_path().with_file_name("cache_clear");
+        persisted(&dir, "prod", Utc::now() + Duration::hours(1));
+        persisted(&dir, "audit", Utc::now() + Duration::hours(1));
+
+        assert_eq!(Cache::new(dir.clone(), "prod").clear(), Ok(true));
+        assert_eq!(Cache::new(dir.clone(), "prod").clear(), Ok(false));
+        assert_eq!(
+            Cache::new(dir.clone(), "prod").clear_all(),
+            Ok(vec!["audit".to_string()])
+        );
+        assert_eq!(Cache::new(dir.clone(), "prod").list(), Ok(vec![]));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_removes_the_expired_sessions() {
+        let dir = fixtures_tmp_path().with_file_name("cache_gc");
+        persisted(&dir, "prod", Utc::now() + Duration::hours(1));
+        persisted(&dir, "audit", Utc::now() - Duration::hours(1));
+        fs::write(
+            dir.join("broken"),
+            "SESSION_EXPIRES_AT=2031-02-03T04:05:06Z",
+        )
+        .unwrap();
+        secure(&dir.join("broken"));
+
+        assert_eq!(
+            Cache::new(dir.clone(), "prod").gc(),
+            Ok(vec!["audit".to_string()])
+        );
+        assert!(dir.join("broken").exists(), "not a session of awsudo");
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .list()
+                .map(|entries| entries.len()),
+            Ok(1)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_writes_the_file_only_readable_by_the_user() {
+        let dir = fixtures_tmp_path().with_file_name("cache_secure");
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        persisted(
+            &dir.join("created"),
+            "prod",
+            Utc::now() + Duration::hours(1),
+        );
+        persisted(&dir, "prod", Utc::now() + Duration::hours(1));
+
+        // Only the dirs it creates are given a mode, not the ones it was given
+        assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o755);
+        assert_eq!(
+            fs::metadata(dir.join("created")).unwrap().mode() & 0o777,
+            0o700
+        );
+        assert_eq!(
+            fs::metadata(dir.join("prod")).unwrap().mode() & 0o777,
+            0o600
+        );
+        assert_eq!(
+            fs::read_dir(&dir).unwrap().count(),
+            2,
+            "the temporary file was moved in place"
+        );
+
+        fs::set_permissions(&dir, Permissions::from_mode(0o777)).unwrap();
+        assert_eq!(
+            Cache::new(dir.clone(), "prod").persist(&Credentials {
+                access_key_id: "A23".to_string(),
+                secret_access_key: "M07".to_string(),
+                session_token: "B03".to_string(),
+                expires_at: Utc::now(),
+                cached: false,
+                cacheable: true,
+            }),
+            Err(Error::CacheDirInsecure)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn it_returns_error_when_the_file_is_readable_by_others() {
+        let dir = fixtures_tmp_path().with_file_name("cache_insecure");
+        persisted(&dir, "prod", Utc::now() + Duration::hours(1));
+        fs::set_permissions(dir.join("prod"), Permissions::from_mode(0o644)).unwrap();
+
+        assert_eq!(
+            Cache::new(dir.clone(), "prod").fetch(),
+            Err(Error::CacheInsecure)
+        );
+
+        // Files of another user, or readable by others, are reported and skipped
+        persisted(&dir, "audit", Utc::now() - Duration::hours(1));
+        fs::set_permissions(dir.join("audit"), Permissions::from_mode(0o644)).unwrap();
+        persisted(&dir, "staging", Utc::now() - Duration::hours(1));
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .list()
+                .map(|entries| entries.into_iter().map(|e| e.profile).collect::<Vec<_>>()),
+            Ok(vec![String::from("staging")])
+        );
+        assert_eq!(
+            Cache::new(dir.clone(), "prod").gc(),
+            Ok(vec![String::from("staging")])
+        );
+        assert!(dir.join("audit").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_stores_the_sessions_in_the_given_backend() {
+        let dir = fixtures_tmp_path().with_file_name("cache_backend");
+        let encrypted = || Box::new(Encrypted::new(b"correct horse".to_vec()));
+        let cr = Credentials {
+            access_key_id: "A23".to_string(),
+            secret_access_key: "M07".to_string(),
+            session_token: "B03".to_string(),
+            expires_at: Utc::now() + Duration::hours(1),
+            cached: false,
+            cacheable: true,
+        };
+
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .with_backend(encrypted())
+                .persist(&cr),
+            Ok(())
+        );
+
+        assert!(Cache::new(dir.clone(), "prod")
+            .with_backend(encrypted())
+            .fetch()
+            .is_ok());
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .with_backend(encrypted())
+                .list()
+                .map(|entries| entries.len()),
+            Ok(1)
+        );
+        assert_eq!(
+            Cache::new(dir.clone(), "prod").fetch(),
+            Err(Error::CacheNotFound)
+        );
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .with_backend(Box::new(Memory))
+                .fetch(),
+            Err(Error::CacheNotFound)
+        );
+
+        // A mistyped passphrase does not wipe the sessions
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .with_backend(Box::new(Encrypted::new(b"battery staple".to_vec())))
+                .gc(),
+            Ok(vec![])
+        );
+        assert!(Cache::new(dir.clone(), "prod")
+            .with_backend(encrypted())
+            .fetch()
+            .is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_returns_error_when_the_settings_do_not_match() {
+        let dir = fixtures_tmp_path().with_file_name("cache_fingerprint");
+        let cr = Credentials {
+            access_key_id: "A23".to_string(),
+            secret_access_key: "M07".to_string(),
+            session_token: "B03".to_string(),
+            expires_at: Utc::now() + Duration::hours(1),
+            cached: false,
+            cacheable: true,
+        };
+
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .with_fingerprint("f1".to_string())
+                .persist(&cr),
+            Ok(())
+        );
+
+        assert!(Cache::new(dir.clone(), "prod")
+            .with_fingerprint("f1".to_string())
+            .fetch()
+            .is_ok());
+        assert_eq!(
+            Cache::new(dir.clone(), "prod")
+                .with_fingerprint("f2".to_string())
+                .fetch(),
+            Err(Error::CacheMismatch)
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_fingerprints_the_resolved_settings() {
+        let chain = |role_arn: &str, duration: Option<i64>| {
+            vec![Profile {
+                role_arn: Some(role_arn.to_string()),
+                mfa_serial: Some("mfa".to_string()),
+                duration_seconds: duration,
+                ..Default::default()
+            }]
+        };
+
+        let fingerprint = cache::fingerprint(&chain("arn", None), &Region::EuWest1);
+
+        assert_eq!(fingerprint.len(), 64);
+        assert_eq!(
+            fingerprint,
+            cache::fingerprint(&chain("arn", None), &Region::EuWest1)
+        );
+        assert_ne!(
+            fingerprint,
+            cache::fingerprint(&chain("other-arn", None), &Region::EuWest1)
+        );
+        assert_ne!(
+            fingerprint,
+            cache::fingerprint(&chain("arn", Some(900)), &Region::EuWest1)
+        );
+        assert_ne!(
+            fingerprint,
+            cache::fingerprint(&chain("arn", None), &Region::UsEast1)
+        );
+    }
+
+    #[test]
+    fn it_waits_for_the_lock_of_the_profile() {
+        let dir = fixtures_tmp_path().with_file_name("cache_lock");
+        let cache = Cache::new(dir.clone(), "prod");
+
+        let lock = cache.lock(Duration::seconds(1).to_std().unwrap()).unwrap();
+        assert!(Cache::new(dir.clone(), "audit")
+            .lock(Duration::milliseconds(200).to_std().unwrap())
+            .is_ok());
+        assert_eq!(
+            cache
+                .lock(Duration::milliseconds(200).to_std().unwrap())
+                .err(),
+            Some(Error::CacheLockTimeout)
+        );
+
+        drop(lock);
+        assert!(cache
+            .lock(Duration::milliseconds(200).to_std().unwrap())
+            .is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_names_the_session_of_the_mfa_device() {
+        assert_eq!(
+            cache::mfa_session_name("arn:aws:iam::123456789012:mfa/jeff"),
+            "mfa-arn_aws_iam__123456789012_mfa_jeff"
+        );
+        assert_ne!(
+            cache::mfa_fingerprint("arn:aws:iam::1:mfa/jeff", None, &Region::EuWest1),
+            cache::mfa_fingerprint("arn:aws:iam::1:mfa/jeff", None, &Region::UsEast1)
+        );
+    }
 }
@@ -1,55 +1,327 @@
 extern crate clap;
 
-use self::clap::{App, AppSettings, Arg, ArgMatches};
+use self::clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
+use awsudo::backend::Kind;
+use awsudo::environment::{CACHE_PASSPHRASE_VARIABLE, PROFILE_VARIABLE};
+use awsudo::export::Syntax;
+use awsudo::profile::Profile;
+use awsudo::sso::Endpoints;
+use std::env;
+use std::ffi::OsString;
+
+const AWS_DEFAULT_PROFILE: &str = "default";
 const AWS_DEFAULT_CONFIG_PATH: &str = ".aws/config";
+const AWS_DEFAULT_CREDENTIALS_PATH: &str = ".aws/credentials";
 const AWS_DEFAULT_CACHE_DIR: &str = ".awsudo/";
 
+/// Operation on the cached sessions, instead of running a command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CacheCommand {
+    List,
+    Show,
+    Clear,
+    ClearAll,
+    Gc,
+}
+
+#[derive(Clone)]
 pub struct CLI {
     pub user: String,
-    pub command: String,
+    pub command: Vec<OsString>,
+    pub shell: bool,
+    pub credentials_server: bool,
+    pub credential_process: bool,
+    pub export: Option<Syntax>,
+    pub clear: bool,
+    pub nest: bool,
+    pub cache_command: Option<CacheCommand>,
+    pub invalidate: bool,
+    pub validate: bool,
     pub config: std::path::PathBuf,
+    pub credentials: std::path::PathBuf,
     pub cache_dir: std::path::PathBuf,
+    pub cache_backend: Kind,
+    pub cache_key_file: Option<std::path::PathBuf>,
+    pub cache_passphrase: Option<Vec<u8>>,
+    pub expiry_window: Option<i64>,
+    pub duration: Option<i64>,
+    pub session_name: Option<String>,
+    pub region: Option<String>,
+    pub sts_endpoint: Option<String>,
+    pub mfa_session: bool,
+    pub mfa_session_duration: Option<i64>,
+    pub web_identity: Option<Profile>,
+    pub login: bool,
+    pub sso_session: Option<String>,
+    pub sso_endpoints: Endpoints,
 }
 
 pub fn parse() -> CLI {
-    from_args(default().get_matches())
+    let matches = default().get_matches();
+    check(&matches).unwrap_or_else(|e| e.exit());
+
+    from_args(matches, &|name| env::var_os(name))
+}
+
+// Conflicts clap cannot tell by itself, the command being an external subcommand
+fn check(matches: &ArgMatches) -> Result<(), clap::Error> {
+    let printing = ["export", "clear"]
+        .iter()
+        .find(|name| matches.is_present(name));
+
+    // Only meaningful when running a command, so never silently dropped by a subcommand
+    let running = [
+        ("shell", "--shell"),
+        ("credentials_server", "--credentials-server"),
+        ("nest", "--nest"),
+        ("invalidate", "-k"),
+        ("validate", "-v"),
+    ]
+    .iter()
+    .find(|(name, _)| matches.is_present(name))
+    .map(|(_, flag)| flag);
+
+    match (printing, running, matches.subcommand_name()) {
+        (Some(name), _, Some(_)) => Err(clap::Error::with_description(
+            &format!(
+                "The argument '--{}' prints the credentials, it cannot be used with a command",
+                name
+            ),
+            clap::ErrorKind::ArgumentConflict,
+        )),
+        (_, Some(name), Some(subcommand))
+            if ["credential-process", "login", "cache"].contains(&subcommand) =>
+        {
+            Err(clap::Error::with_description(
+                &format!(
+                    "The argument '{}' runs a command, it cannot be used with the {} subcommand",
+                    name, subcommand
+                ),
+                clap::ErrorKind::ArgumentConflict,
+            ))
+        }
+        _ => Ok(()),
+    }
 }
 
-fn from_args(matches: ArgMatches) -> CLI {
-    let user = String::from(matches.value_of("user").unwrap_or("default"));
+/// Builds the settings from the arguments, falling back to the environment, read through
+/// `environment`, and then to the defaults.
+fn from_args(matches: ArgMatches, environment: &dyn Fn(&str) -> Option<OsString>) -> CLI {
+    // The credential-process subcommand takes the same role options as the command runner,
+    // the cache and login subcommands only the ones needed to find the sessions. Options given
+    // before the subcommand still apply, the ones given after it take precedence.
+    let (credential_process, login, cache_command, scopes) = match matches.subcommand() {
+        ("credential-process", Some(sub_matches)) => {
+            (true, false, None, vec![sub_matches, &matches])
+        }
+        ("login", Some(sub_matches)) => (false, true, None, vec![sub_matches, &matches]),
+        ("cache", Some(sub_matches)) => {
+            let (name, scopes) = match sub_matches.subcommand() {
+                (name, Some(cache_matches)) => (name, vec![cache_matches, &matches]),
+                (name, None) => (name, vec![&matches]),
+            };
+            let cache_command = match name {
+                "list" => CacheCommand::List,
+                "show" => CacheCommand::Show,
+                "clear" if scopes[0].is_present("all") => CacheCommand::ClearAll,
+                "clear" => CacheCommand::Clear,
+                _ => CacheCommand::Gc,
+            };
+
+            (false, false, Some(cache_command), scopes)
+        }
+        _ => (false, false, None, vec![&matches]),
+    };
+    let matches = Scopes(scopes);
+
+    let user = matches
+        .value_of_os("user")
+        .map(OsString::from)
+        .or_else(|| environment(PROFILE_VARIABLE))
+        .or_else(|| environment("AWSUDO_DEFAULT_PROFILE"))
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from(AWS_DEFAULT_PROFILE));
+
     let config = matches
         .value_of("config")
         .map(|s| std::path::PathBuf::from(s))
+        .or_else(|| environment("AWSUDO_CONFIG").map(std::path::PathBuf::from))
+        .or_else(|| environment("AWS_CONFIG_FILE").map(std::path::PathBuf::from))
         .or(dirs::home_dir().map(|path| path.join(AWS_DEFAULT_CONFIG_PATH)))
         .expect("Something wrong with config");
 
+    let credentials = environment("AWS_SHARED_CREDENTIALS_FILE")
+        .map(std::path::PathBuf::from)
+        .or(dirs::home_dir().map(|path| path.join(AWS_DEFAULT_CREDENTIALS_PATH)))
+        .expect("Something wrong with credentials");
+
     let cache_dir = matches
         .value_of("cache_dir")
         .map(|s| std::path::PathBuf::from(s))
+        .or_else(|| environment("AWSUDO_CACHE_DIR").map(std::path::PathBuf::from))
         .or(dirs::runtime_dir().map(|path| path.join(AWS_DEFAULT_CACHE_DIR)))
         .or(dirs::home_dir().map(|path| path.join(AWS_DEFAULT_CACHE_DIR)))
         .expect("Something wrong with cache_dir");
 
-    let command = match matches.subcommand() {
+    let cache_backend = matches
+        .value_of_os("cache_backend")
+        .map(OsString::from)
+        .or_else(|| environment("AWSUDO_CACHE_BACKEND"))
+        .map(|s| {
+            // The option is checked by clap, only the environment can still hold anything else
+            s.to_string_lossy().parse::<Kind>().unwrap_or_else(|e| {
+                clap::Error::with_description(
+                    &format!("{}, expected one of: plaintext, encrypted, memory", e),
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit()
+            })
+        })
+        .unwrap_or(Kind::Plaintext);
+
+    let cache_key_file = matches
+        .value_of("cache_key_file")
+        .map(std::path::PathBuf::from)
+        .or_else(|| environment("AWSUDO_CACHE_KEY_FILE").map(std::path::PathBuf::from));
+
+    // A key file takes precedence, read once the backend is actually needed
+    let cache_passphrase = match cache_key_file {
+        Some(_) => None,
+        None => environment(CACHE_PASSPHRASE_VARIABLE)
+            .map(|s| s.to_string_lossy().into_owned().into_bytes()),
+    };
+
+    let expiry_window = matches
+        .value_of("expiry_window")
+        .and_then(|s| s.parse::<i64>().ok());
+
+    let duration = matches
+        .value_of("duration")
+        .and_then(|s| s.parse::<i64>().ok());
+
+    let session_name = matches.value_of("session_name").map(String::from);
+
+    let region = matches.value_of("region").map(String::from);
+
+    let sts_endpoint = matches.value_of("sts_endpoint").map(String::from);
+
+    // Set up by CI runners and EKS, the web identity is a source of credentials of its own
+    let web_identity = match (
+        environment("AWS_ROLE_ARN"),
+        environment("AWS_WEB_IDENTITY_TOKEN_FILE"),
+    ) {
+        (Some(role_arn), Some(token_file)) => Some(Profile::web_identity(
+            role_arn.to_string_lossy().into_owned(),
+            token_file.to_string_lossy().into_owned(),
+            environment("AWS_ROLE_SESSION_NAME").map(|s| s.to_string_lossy().into_owned()),
+        )),
+        _ => None,
+    };
+
+    let sso_session = matches.value_of("sso_session").map(String::from);
+
+    let sso_endpoints = Endpoints {
+        oidc: matches
+            .value_of_os("sso_oidc_endpoint")
+            .map(OsString::from)
+            .or_else(|| environment("AWS_ENDPOINT_URL_SSO_OIDC"))
+            .map(|s| s.to_string_lossy().into_owned()),
+        portal: matches
+            .value_of_os("sso_endpoint")
+            .map(OsString::from)
+            .or_else(|| environment("AWS_ENDPOINT_URL_SSO"))
+            .map(|s| s.to_string_lossy().into_owned()),
+    };
+
+    let mfa_session = !matches.is_present("no_mfa_session");
+
+    let mfa_session_duration = matches
+        .value_of("mfa_session_duration")
+        .and_then(|s| s.parse::<i64>().ok());
+
+    let shell = matches.is_present("shell");
+
+    let credentials_server = matches.is_present("credentials_server");
+
+    let command = match matches.0[0].subcommand() {
+        ("", _) => vec![],
         (external, maybe_matches) => {
-            let args = match maybe_matches {
-                Some(external_matches) => match external_matches.values_of("") {
-                    Some(values) => values.collect::<Vec<&str>>().join(" "),
-                    None => String::from(""),
-                },
-                _ => String::from(""),
-            };
+            let mut command = vec![OsString::from(external)];
+
+            if let Some(values) = maybe_matches.and_then(|m| m.values_of_os("")) {
+                command.extend(values.map(OsString::from));
+            }
 
-            String::from(vec![String::from(external), args].join(" ").trim())
+            command
         }
     };
 
+    let clear = matches.is_present("clear");
+
+    let nest = matches.is_present("nest");
+
+    let invalidate = matches.is_present("invalidate");
+
+    let validate = matches.is_present("validate");
+
+    let export = if matches.is_present("export") || clear {
+        Some(
+            matches
+                .value_of("export_shell")
+                .and_then(|s| s.parse::<Syntax>().ok())
+                .unwrap_or_else(Syntax::detect),
+        )
+    } else {
+        None
+    };
+
     CLI {
         user,
         config,
+        credentials,
         command,
+        shell,
+        credentials_server,
+        credential_process,
+        export,
+        clear,
+        nest,
+        cache_command,
+        invalidate,
+        validate,
         cache_dir,
+        cache_backend,
+        cache_key_file,
+        cache_passphrase,
+        expiry_window,
+        duration,
+        session_name,
+        region,
+        sts_endpoint,
+        mfa_session,
+        mfa_session_duration,
+        web_identity,
+        login,
+        sso_session,
+        sso_endpoints,
+    }
+}
+
+// The matches of a subcommand, then the ones of its parents
+struct Scopes<'a, 'b: 'a>(Vec<&'a ArgMatches<'b>>);
+
+impl<'a, 'b> Scopes<'a, 'b> {
+    fn value_of(&self, name: &str) -> Option<&'a str> {
+        self.0.iter().filter_map(|m| m.value_of(name)).next()
+    }
+
+    fn value_of_os(&self, name: &str) -> Option<&'a std::ffi::OsStr> {
+        self.0.iter().filter_map(|m| m.value_of_os(name)).next()
+    }
+
+    fn is_present(&self, name: &str) -> bool {
+        self.0.iter().any(|m| m.is_present(name))
     }
 }
 
@@ -57,46 +329,253 @@ fn default<'b, 'c>() -> App<'b, 'c> {
     App::new("awsudo - sudo-like behavior for role assumed access on AWS accounts")
         .version(clap::crate_version!())
         .setting(AppSettings::AllowExternalSubcommands)
+        .args(&options())
         .arg(
-            Arg::with_name("config")
-                .short("c")
-                .long("config")
-                .value_name("FILE")
-                .help("Custom config file, defaults to: ~/.aws/config")
-                .takes_value(true),
+            Arg::with_name("credentials_server")
+                .long("credentials-server")
+                .help("Serve auto-refreshing credentials to the command through a local container credentials endpoint"),
         )
         .arg(
-            Arg::with_name("cache_dir")
-                .long("cache-dir")
-                .value_name("DIR")
-                .help("Custom directory for credentials caching, defaults to ~/.awsudo/")
-                .takes_value(true),
+            Arg::with_name("export")
+                .long("export")
+                .conflicts_with("shell")
+                .help("Print the credentials as variables for the shell given with --export-shell, defaults to $SHELL"),
+        )
+        .arg(
+            Arg::with_name("clear")
+                .long("clear")
+                .conflicts_with("shell")
+                .help("Print the statements removing the variables set by --export"),
         )
         .arg(
-            Arg::with_name("user")
-                .short("u")
-                .long("user")
-                .help("AWS profile name based on the config file")
-                .required(true)
+            Arg::with_name("export_shell")
+                .long("export-shell")
+                .value_name("SYNTAX")
+                .possible_values(&["bash", "zsh", "fish", "powershell", "dotenv", "json"])
+                .help("Syntax of --export and --clear, defaults to the one of $SHELL")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("invalidate")
+                .short("k")
+                .help("Remove the cached session of the profile, starting a new one if given a command"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .short("v")
+                .help("Refresh the cached session of the profile without running a command"),
+        )
+        .arg(
+            Arg::with_name("nest")
+                .long("nest")
+                .help("Allow starting a shell from within an awsudo shell of another profile"),
+        )
+        .arg(
+            Arg::with_name("shell")
+                .short("s")
+                .long("shell")
+                .help("Run the command through $SHELL -c instead of executing it directly"),
+        )
+        .subcommand(
+            SubCommand::with_name("credential-process")
+                .about("Print the credentials in the format expected by the credential_process setting")
+                .args(&options()),
+        )
+        .subcommand(
+            SubCommand::with_name("login")
+                .about("Sign in to the SSO session of the profile, or the one given with --sso-session")
+                .arg(user().conflicts_with("sso_session"))
+                .arg(config())
+                .arg(
+                    Arg::with_name("sso_session")
+                        .long("sso-session")
+                        .value_name("NAME")
+                        .help("Name of the [sso-session] section of the config file to sign in to")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("sso_oidc_endpoint")
+                        .long("sso-oidc-endpoint")
+                        .value_name("URL")
+                        .help("Custom SSO OIDC endpoint, overrides AWS_ENDPOINT_URL_SSO_OIDC")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache")
+                .about("Manage the cached sessions")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the cached sessions with their remaining lifetime")
+                        .args(&cache()),
+                )
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Show the cached session of the profile")
+                        .arg(user())
+                        .args(&cache()),
+                )
+                .subcommand(
+                    SubCommand::with_name("clear")
+                        .about("Remove the cached session of the profile")
+                        .arg(user())
+                        .args(&cache())
+                        .arg(
+                            Arg::with_name("all")
+                                .long("all")
+                                .conflicts_with("user")
+                                .help("Remove every cached session"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("gc")
+                        .about("Remove the expired cached sessions")
+                        .args(&cache()),
+                ),
+        )
+}
+
+fn options<'b, 'c>() -> Vec<Arg<'b, 'c>> {
+    vec![
+        config(),
+        Arg::with_name("expiry_window")
+            .long("expiry-window")
+            .value_name("SECONDS")
+            .help("Consider cached credentials expired this long before they actually expire, defaults to 300")
+            .validator(seconds)
+            .takes_value(true),
+        Arg::with_name("duration")
+            .long("duration")
+            .value_name("SECONDS")
+            .help("Duration of the role session, overrides duration_seconds from the profile")
+            .validator(seconds)
+            .takes_value(true),
+        Arg::with_name("region")
+            .long("region")
+            .value_name("REGION")
+            .help("Region of the STS endpoint, overrides AWS_REGION and the profile region")
+            .takes_value(true),
+        Arg::with_name("sts_endpoint")
+            .long("sts-endpoint")
+            .value_name("URL")
+            .help("Custom STS endpoint, overrides AWS_ENDPOINT_URL_STS and the profile endpoint_url")
+            .takes_value(true),
+        Arg::with_name("sso_endpoint")
+            .long("sso-endpoint")
+            .value_name("URL")
+            .help("Custom SSO portal endpoint, overrides AWS_ENDPOINT_URL_SSO")
+            .takes_value(true),
+        Arg::with_name("session_name")
+            .long("session-name")
+            .value_name("NAME")
+            .help("Name of the role session, overrides role_session_name from the profile")
+            .takes_value(true),
+        Arg::with_name("mfa_session_duration")
+            .long("mfa-session-duration")
+            .value_name("SECONDS")
+            .help("Duration of the session of the MFA device, from 900 to 129600, defaults to 43200")
+            .validator(seconds)
+            .takes_value(true),
+        Arg::with_name("no_mfa_session")
+            .long("no-mfa-session")
+            .conflicts_with("mfa_session_duration")
+            .help("Ask for the MFA token for every role instead of reusing a session of the device"),
+        user(),
+    ]
+    .into_iter()
+    .chain(cache())
+    .collect()
+}
+
+// Rejects values that are not a number of seconds with a usage error
+fn seconds(value: String) -> Result<(), String> {
+    value
+        .parse::<i64>()
+        .map(|_| ())
+        .map_err(|_| format!("{} is not a number of seconds", value))
+}
+
+fn config<'b, 'c>() -> Arg<'b, 'c> {
+    Arg::with_name("config")
+        .short("c")
+        .long("config")
+        .value_name("FILE")
+        .help("Custom config file, defaults to: $AWSUDO_CONFIG, $AWS_CONFIG_FILE or ~/.aws/config")
+        .takes_value(true)
+}
+
+fn user<'b, 'c>() -> Arg<'b, 'c> {
+    Arg::with_name("user")
+        .short("u")
+        .long("user")
+        .help("AWS profile name based on the config file, defaults to: $AWS_PROFILE, $AWSUDO_DEFAULT_PROFILE or default")
+        .takes_value(true)
+}
+
+fn cache<'b, 'c>() -> Vec<Arg<'b, 'c>> {
+    vec![
+        Arg::with_name("cache_dir")
+            .long("cache-dir")
+            .value_name("DIR")
+            .help(
+                "Custom directory for credentials caching, defaults to $AWSUDO_CACHE_DIR or ~/.awsudo/",
+            )
+            .takes_value(true),
+        Arg::with_name("cache_backend")
+            .long("cache-backend")
+            .value_name("BACKEND")
+            .possible_values(&["plaintext", "encrypted", "memory"])
+            .help("Storage of the cached sessions, defaults to $AWSUDO_CACHE_BACKEND or plaintext")
+            .takes_value(true),
+        Arg::with_name("cache_key_file")
+            .long("cache-key-file")
+            .value_name("FILE")
+            .help("Passphrase file of the encrypted cache, defaults to $AWSUDO_CACHE_KEY_FILE, $AWSUDO_CACHE_PASSPHRASE or a prompt")
+            .takes_value(true),
+    ]
 }
 
 #[cfg(test)]
 mod tests {
+    use awsudo::backend::Kind;
     use awsudo::cli;
+    use awsudo::cli::CacheCommand;
+    use awsudo::export::Syntax;
+    use awsudo::sso::Endpoints;
+    use std::ffi::OsString;
     use std::path::PathBuf;
 
+    fn no_env(_: &str) -> Option<OsString> {
+        None
+    }
+
     #[test]
     fn it_parses_user() {
-        let result = cli::from_args(cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]));
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]),
+            &no_env,
+        );
 
         assert_eq!(result.user, "jeff");
     }
 
+    #[test]
+    fn it_defaults_user() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "echo"]),
+            &no_env,
+        );
+
+        assert_eq!(result.user, "default");
+    }
+
     #[test]
     fn it_sets_default_cache_dir() {
-        let result = cli::from_args(cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]));
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]),
+            &no_env,
+        );
 
         let dir = match dirs::runtime_dir() {
             None => dirs::home_dir().unwrap().join(".awsudo/"),
@@ -108,62 +587,590 @@ mod tests {
 
     #[test]
     fn it_accepts_cache_dir_option() {
-        let result = cli::from_args(cli::default().get_matches_from(vec![
-            "awsudo",
-            "-u",
-            "jeff",
-            "--cache-dir",
-            "/foo/bar",
-        ]));
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--cache-dir",
+                "/foo/bar",
+            ]),
+            &no_env,
+        );
 
         assert_eq!(result.cache_dir, PathBuf::from("/foo/bar"));
     }
 
+    #[test]
+    fn it_accepts_expiry_window_option() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "--expiry-window", "60"]),
+            &no_env,
+        );
+
+        assert_eq!(result.expiry_window, Some(60));
+        assert!(cli::default()
+            .get_matches_from_safe(vec!["awsudo", "-u", "jeff", "--expiry-window", "1m"])
+            .is_err());
+    }
+
+    #[test]
+    fn it_accepts_session_options() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--duration",
+                "900",
+                "--session-name",
+                "bezos",
+            ]),
+            &no_env,
+        );
+
+        assert_eq!(result.duration, Some(900));
+        assert_eq!(result.session_name, Some(String::from("bezos")));
+        assert!(cli::default()
+            .get_matches_from_safe(vec!["awsudo", "-u", "jeff", "--duration", "1h"])
+            .is_err());
+    }
+
+    #[test]
+    fn it_accepts_region_option() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "--region", "us-west-2"]),
+            &no_env,
+        );
+
+        assert_eq!(result.region, Some(String::from("us-west-2")));
+    }
+
+    #[test]
+    fn it_accepts_sts_endpoint_option() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--sts-endpoint",
+                "http://localhost:4566",
+            ]),
+            &no_env,
+        );
+
+        assert_eq!(
+            result.sts_endpoint,
+            Some(String::from("http://localhost:4566"))
+        );
+    }
+
+    #[test]
+    fn it_parses_credentials_server_option() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--credentials-server",
+                "terraform",
+                "apply",
+            ]),
+            &no_env,
+        );
+
+        assert!(result.credentials_server);
+        assert_eq!(result.command, vec!["terraform", "apply"]);
+    }
+
     #[test]
     fn it_parses_config() {
-        let result = cli::from_args(cli::default().get_matches_from(vec![
-            "awsudo",
-            "-u",
-            "jeff",
-            "-c",
-            "/usr/specific/path",
-        ]));
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "-c",
+                "/usr/specific/path",
+            ]),
+            &no_env,
+        );
 
         assert_eq!(result.config, PathBuf::from("/usr/specific/path"));
     }
 
     #[test]
     fn it_parses_single_command() {
-        let result =
-            cli::from_args(cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "echo"]));
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "echo"]),
+            &no_env,
+        );
 
-        assert_eq!(result.command, "echo");
+        assert_eq!(result.command, vec!["echo"]);
     }
 
     #[test]
     fn it_parses_command_with_multiple_words() {
         let result = cli::from_args(
             cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "echo", "bezos", "aws"]),
+            &no_env,
         );
 
-        assert_eq!(result.command, "echo bezos aws");
+        assert_eq!(result.command, vec!["echo", "bezos", "aws"]);
     }
 
     #[test]
     fn it_parses_command_with_attribute() {
         let result = cli::from_args(
             cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "ls", "-a"]),
+            &no_env,
         );
 
-        assert_eq!(result.command, "ls -a");
+        assert_eq!(result.command, vec!["ls", "-a"]);
     }
 
     #[test]
     fn it_parses_command_with_multiple_attributes() {
         let result = cli::from_args(
             cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "ls", "-a", "-l"]),
+            &no_env,
+        );
+
+        assert_eq!(result.command, vec!["ls", "-a", "-l"]);
+    }
+
+    #[test]
+    fn it_parses_no_command() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]),
+            &no_env,
+        );
+
+        assert_eq!(result.command, Vec::<OsString>::new());
+        assert!(!result.shell);
+    }
+
+    #[test]
+    fn it_keeps_arguments_with_spaces_and_shell_characters() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "aws",
+                "s3",
+                "cp",
+                "my file.txt",
+                "$HOME;ls",
+            ]),
+            &no_env,
+        );
+
+        assert_eq!(
+            result.command,
+            vec!["aws", "s3", "cp", "my file.txt", "$HOME;ls"]
+        );
+    }
+
+    #[test]
+    fn it_parses_shell_option() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "--shell", "echo $HOME"]),
+            &no_env,
+        );
+
+        assert!(result.shell);
+        assert_eq!(result.command, vec!["echo $HOME"]);
+    }
+
+    #[test]
+    fn it_parses_credential_process_subcommand() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "credential-process",
+                "-u",
+                "jeff",
+                "--region",
+                "us-west-2",
+            ]),
+            &no_env,
+        );
+
+        assert!(result.credential_process);
+        assert_eq!(result.user, "jeff");
+        assert_eq!(result.region, Some(String::from("us-west-2")));
+        assert_eq!(result.command, Vec::<OsString>::new());
+    }
+
+    #[test]
+    fn it_keeps_the_options_given_before_the_credential_process_subcommand() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--region",
+                "us-west-2",
+                "credential-process",
+                "--region",
+                "eu-west-1",
+            ]),
+            &no_env,
+        );
+
+        assert!(result.credential_process);
+        assert_eq!(result.user, "jeff");
+        assert_eq!(result.region, Some(String::from("eu-west-1")));
+
+        let checked = |args: Vec<&str>| {
+            cli::default()
+                .get_matches_from_safe(args)
+                .and_then(|matches| cli::check(&matches))
+        };
+        assert!(checked(vec!["awsudo", "-s", "credential-process"]).is_err());
+        assert!(checked(vec!["awsudo", "-s", "echo", "hi"]).is_ok());
+    }
+
+    #[test]
+    fn it_parses_export_option_with_shell() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--export",
+                "--export-shell",
+                "fish",
+            ]),
+            &no_env,
+        );
+
+        assert_eq!(result.export, Some(Syntax::Fish));
+        assert!(!result.clear);
+        assert_eq!(result.command, Vec::<OsString>::new());
+    }
+
+    #[test]
+    fn it_refuses_a_command_or_unknown_syntax_when_exporting() {
+        let checked = |args: Vec<&str>| {
+            cli::default()
+                .get_matches_from_safe(args)
+                .and_then(|matches| cli::check(&matches))
+        };
+
+        assert!(checked(vec!["awsudo", "-u", "jeff", "--export", "echo", "hi"]).is_err());
+        assert!(checked(vec!["awsudo", "-u", "jeff", "--shell", "--export"]).is_err());
+        assert!(checked(vec!["awsudo", "--clear", "--export-shell", "xyz"]).is_err());
+        assert!(checked(vec!["awsudo", "-u", "jeff", "--export"]).is_ok());
+    }
+
+    #[test]
+    fn it_parses_clear_option() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "--clear", "--export-shell", "dotenv"]),
+            &no_env,
+        );
+
+        assert_eq!(result.export, Some(Syntax::Dotenv));
+        assert!(result.clear);
+    }
+
+    #[test]
+    fn it_parses_nest_option() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]),
+            &no_env,
+        );
+        assert!(!result.nest);
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "--nest"]),
+            &no_env,
+        );
+        assert!(result.nest);
+    }
+
+    #[test]
+    fn it_falls_back_to_the_environment() {
+        let env = |name: &str| match name {
+            "AWS_PROFILE" => Some(OsString::from("bezos")),
+            "AWSUDO_DEFAULT_PROFILE" => Some(OsString::from("jeff")),
+            "AWS_CONFIG_FILE" => Some(OsString::from("/aws/config")),
+            "AWSUDO_CACHE_DIR" => Some(OsString::from("/awsudo/cache")),
+            "AWS_SHARED_CREDENTIALS_FILE" => Some(OsString::from("/aws/credentials")),
+            "AWS_ROLE_ARN" => Some(OsString::from("arn:aws:iam::1:role/ci")),
+            "AWS_WEB_IDENTITY_TOKEN_FILE" => Some(OsString::from("/var/run/token")),
+            _ => None,
+        };
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "echo"]),
+            &env,
+        );
+
+        assert_eq!(result.user, "bezos");
+        assert_eq!(result.config, PathBuf::from("/aws/config"));
+        assert_eq!(result.credentials, PathBuf::from("/aws/credentials"));
+        assert_eq!(result.cache_dir, PathBuf::from("/awsudo/cache"));
+        assert_eq!(
+            result
+                .web_identity
+                .map(|p| (p.role_arn, p.web_identity_token_file)),
+            Some((
+                Some(String::from("arn:aws:iam::1:role/ci")),
+                Some(String::from("/var/run/token"))
+            ))
+        );
+    }
+
+    #[test]
+    fn it_prefers_the_awsudo_environment() {
+        let env = |name: &str| match name {
+            "AWSUDO_DEFAULT_PROFILE" => Some(OsString::from("jeff")),
+            "AWSUDO_CONFIG" => Some(OsString::from("/awsudo/config")),
+            "AWS_CONFIG_FILE" => Some(OsString::from("/aws/config")),
+            _ => None,
+        };
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "echo"]),
+            &env,
+        );
+
+        assert_eq!(result.user, "jeff");
+        assert_eq!(result.config, PathBuf::from("/awsudo/config"));
+    }
+
+    #[test]
+    fn it_prefers_the_arguments_over_the_environment() {
+        let env = |name: &str| match name {
+            "AWS_PROFILE" => Some(OsString::from("bezos")),
+            "AWSUDO_CONFIG" => Some(OsString::from("/awsudo/config")),
+            "AWSUDO_CACHE_DIR" => Some(OsString::from("/awsudo/cache")),
+            _ => None,
+        };
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "-c",
+                "/usr/specific/path",
+                "--cache-dir",
+                "/foo/bar",
+            ]),
+            &env,
+        );
+
+        assert_eq!(result.user, "jeff");
+        assert_eq!(result.config, PathBuf::from("/usr/specific/path"));
+        assert_eq!(result.cache_dir, PathBuf::from("/foo/bar"));
+    }
+
+    #[test]
+    fn it_parses_cache_subcommands() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "cache", "list", "--cache-dir", "/foo"]),
+            &no_env,
+        );
+        assert_eq!(result.cache_command, Some(CacheCommand::List));
+        assert_eq!(result.cache_dir, PathBuf::from("/foo"));
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "cache", "clear", "-u", "jeff"]),
+            &no_env,
+        );
+        assert_eq!(result.cache_command, Some(CacheCommand::Clear));
+        assert_eq!(result.user, "jeff");
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "cache", "clear", "--all"]),
+            &no_env,
+        );
+        assert_eq!(result.cache_command, Some(CacheCommand::ClearAll));
+    }
+
+    #[test]
+    fn it_keeps_the_options_given_before_the_cache_subcommands() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "--cache-dir", "/foo", "cache", "list"]),
+            &no_env,
+        );
+        assert_eq!(result.cache_command, Some(CacheCommand::List));
+        assert_eq!(result.cache_dir, PathBuf::from("/foo"));
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--cache-dir",
+                "/foo",
+                "cache",
+                "clear",
+                "--cache-dir",
+                "/bar",
+            ]),
+            &no_env,
+        );
+        assert_eq!(result.cache_command, Some(CacheCommand::Clear));
+        assert_eq!(result.user, "jeff");
+        assert_eq!(result.cache_dir, PathBuf::from("/bar"));
+
+        assert!(cli::default()
+            .get_matches_from_safe(vec!["awsudo", "-k", "cache", "gc"])
+            .and_then(|matches| cli::check(&matches))
+            .is_err());
+    }
+
+    #[test]
+    fn it_parses_invalidate_and_validate_options() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "-k", "ls"]),
+            &no_env,
+        );
+        assert!(result.invalidate);
+        assert!(!result.validate);
+        assert_eq!(result.command, vec!["ls"]);
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "-v"]),
+            &no_env,
+        );
+        assert!(result.validate);
+        assert_eq!(result.cache_command, None);
+    }
+
+    #[test]
+    fn it_parses_cache_backend_options() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]),
+            &no_env,
+        );
+        assert_eq!(result.cache_backend, Kind::Plaintext);
+
+        let env = |name: &str| match name {
+            "AWSUDO_CACHE_BACKEND" => Some(OsString::from("memory")),
+            "AWSUDO_CACHE_PASSPHRASE" => Some(OsString::from("correct horse")),
+            _ => None,
+        };
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]),
+            &env,
+        );
+        assert_eq!(result.cache_backend, Kind::Memory);
+        assert_eq!(result.cache_passphrase, Some(b"correct horse".to_vec()));
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "cache",
+                "list",
+                "--cache-backend",
+                "encrypted",
+                "--cache-key-file",
+                "/foo/key",
+            ]),
+            &env,
+        );
+        assert_eq!(result.cache_backend, Kind::Encrypted);
+        assert_eq!(result.cache_key_file, Some(PathBuf::from("/foo/key")));
+        assert_eq!(result.cache_passphrase, None);
+    }
+
+    #[test]
+    fn it_parses_mfa_session_options() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff"]),
+            &no_env,
+        );
+        assert!(result.mfa_session);
+        assert_eq!(result.mfa_session_duration, None);
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "jeff",
+                "--mfa-session-duration",
+                "129600",
+            ]),
+            &no_env,
+        );
+        assert_eq!(result.mfa_session_duration, Some(129600));
+        assert!(cli::default()
+            .get_matches_from_safe(vec!["awsudo", "--mfa-session-duration", "12h"])
+            .is_err());
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "jeff", "--no-mfa-session"]),
+            &no_env,
+        );
+        assert!(!result.mfa_session);
+    }
+
+    #[test]
+    fn it_parses_login_subcommand() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "login", "--sso-session", "my-sso"]),
+            &no_env,
+        );
+        assert!(result.login);
+        assert_eq!(result.sso_session, Some(String::from("my-sso")));
+        assert!(result.command.is_empty());
+
+        let env = |name: &str| match name {
+            "AWS_ENDPOINT_URL_SSO" => Some(OsString::from("http://localhost:4000")),
+            "AWS_ENDPOINT_URL_SSO_OIDC" => Some(OsString::from("http://localhost:5000")),
+            _ => None,
+        };
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "login",
+                "-u",
+                "dev",
+                "--sso-oidc-endpoint",
+                "http://localhost:6000",
+            ]),
+            &env,
+        );
+        assert_eq!(result.user, "dev");
+        assert_eq!(result.sso_session, None);
+        assert_eq!(
+            result.sso_endpoints,
+            Endpoints {
+                oidc: Some(String::from("http://localhost:6000")),
+                portal: Some(String::from("http://localhost:4000")),
+            }
+        );
+
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec!["awsudo", "-u", "dev", "ls"]),
+            &no_env,
+        );
+        assert!(!result.login);
+        assert_eq!(result.sso_endpoints, Endpoints::default());
+    }
+
+    #[test]
+    fn it_keeps_the_options_given_before_the_login_subcommand() {
+        let result = cli::from_args(
+            cli::default().get_matches_from(vec![
+                "awsudo",
+                "-u",
+                "dev",
+                "-c",
+                "/foo/config",
+                "login",
+            ]),
+            &no_env,
         );
 
-        assert_eq!(result.command, "ls -a -l");
+        assert!(result.login);
+        assert_eq!(result.user, "dev");
+        assert_eq!(result.config, PathBuf::from("/foo/config"));
     }
 }
@@ -0,0 +1,195 @@
+extern crate chrono;
+extern crate serde_json;
+
+use self::chrono::{DateTime, Utc};
+use self::serde_json::{json, Value};
+
+use awsudo::credentials::Credentials;
+use awsudo::error::Error;
+use awsudo::profile::StaticCredentials;
+use std::process::{Command, Stdio};
+
+const CREDENTIAL_PROCESS_VERSION: u8 = 1;
+
+/// Credentials printed by the `credential_process` of a profile, long-term keys when they have
+/// no session token.
+#[derive(Debug, PartialEq)]
+pub struct Process {
+    pub keys: StaticCredentials,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Process {
+    /// Temporary credentials, which can be used as they are. Without an `Expiration` they are
+    /// not cached, so the helper is run again the next time.
+    pub fn credentials(&self) -> Option<Credentials> {
+        Credentials::temporary(&self.keys, self.expires_at)
+    }
+}
+
+/// Runs the helper through `sh -c`, letting it interact with the user on the terminal, and
+/// reads the credentials it prints.
+pub fn run(command: &str) -> Result<Process, Error> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| Error::CredentialProcessFailed(e.to_string()))?;
+
+    if !output.status.success() {
+        return Err(Error::CredentialProcessFailed(output.status.to_string()));
+    }
+
+    parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Reads the output of a `credential_process`, in the format of the AWS CLI and SDKs.
+pub fn parse(output: &str) -> Result<Process, Error> {
+    let document: Value =
+        serde_json::from_str(output).map_err(|_| Error::CredentialProcessInvalid)?;
+    let string = |key: &str| document[key].as_str().map(String::from);
+
+    let expires_at = match string("Expiration") {
+        Some(raw) => Some(
+            raw.parse::<DateTime<Utc>>()
+                .map_err(|_| Error::CredentialProcessInvalid)?,
+        ),
+        None => None,
+    };
+
+    match (
+        document["Version"].as_u64(),
+        string("AccessKeyId"),
+        string("SecretAccessKey"),
+    ) {
+        (Some(version), Some(access_key_id), Some(secret_access_key))
+            if version == u64::from(CREDENTIAL_PROCESS_VERSION) =>
+        {
+            Ok(Process {
+                keys: StaticCredentials {
+                    access_key_id,
+                    secret_access_key,
+                    session_token: string("SessionToken"),
+                },
+                expires_at,
+            })
+        }
+        _ => Err(Error::CredentialProcessInvalid),
+    }
+}
+
+impl Credentials {
+    /// Formats the credentials as expected from a `credential_process` by the AWS CLI and SDKs.
+    pub fn to_credential_process(&self) -> String {
+        json!({
+            "Version": CREDENTIAL_PROCESS_VERSION,
+            "AccessKeyId": self.access_key_id,
+            "SecretAccessKey": self.secret_access_key,
+            "SessionToken": self.session_token,
+            "Expiration": self.expires_at.to_rfc3339(),
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::credential_process;
+    use awsudo::credential_process::chrono::{Duration, TimeZone, Utc};
+    use awsudo::credential_process::serde_json::{self, Value};
+    use awsudo::credentials::Credentials;
+    use awsudo::error::Error;
+
+    #[test]
+    fn it_formats_the_credentials_for_credential_process() {
+        let output = Credentials {
+            access_key_id: "A23".to_string(),
+            secret_access_key: "M07".to_string(),
+            session_token: "B03".to_string(),
+            expires_at: "2031-02-03T04:05:06Z".parse().unwrap(),
+            cached: true,
+            cacheable: true,
+        }
+        .to_credential_process();
+
+        let document: Value = serde_json::from_str(&output).unwrap();
+
+        assert_eq!(document["Version"], 1);
+        assert_eq!(document["AccessKeyId"], "A23");
+        assert_eq!(document["SecretAccessKey"], "M07");
+        assert_eq!(document["SessionToken"], "B03");
+        assert_eq!(document["Expiration"], "2031-02-03T04:05:06+00:00");
+    }
+
+    #[test]
+    fn it_reads_the_credentials_printed_by_the_helper() {
+        let process = credential_process::run(
+            "echo '{\"Version\": 1, \"AccessKeyId\": \"A23\", \"SecretAccessKey\": \"M07\", \
+             \"SessionToken\": \"B03\", \"Expiration\": \"2031-02-03T04:05:06Z\"}'",
+        )
+        .unwrap();
+
+        assert_eq!(process.keys.access_key_id, "A23");
+        assert_eq!(
+            process
+                .credentials()
+                .map(|c| (c.session_token, c.expires_at)),
+            Some(("B03".to_string(), Utc.ymd(2031, 2, 3).and_hms(4, 5, 6)))
+        );
+    }
+
+    #[test]
+    fn it_keeps_long_term_keys_as_source_keys() {
+        let process = credential_process::parse(
+            "{\"Version\": 1, \"AccessKeyId\": \"AKIA\", \"SecretAccessKey\": \"M07\"}",
+        )
+        .unwrap();
+
+        assert_eq!(process.keys.session_token, None);
+        assert_eq!(process.expires_at, None);
+        assert_eq!(process.credentials(), None);
+    }
+
+    #[test]
+    fn it_keeps_temporary_credentials_without_expiration_out_of_the_cache() {
+        let credentials = credential_process::parse(
+            "{\"Version\": 1, \"AccessKeyId\": \"A23\", \"SecretAccessKey\": \"M07\", \
+             \"SessionToken\": \"B03\"}",
+        )
+        .unwrap()
+        .credentials()
+        .unwrap();
+
+        assert!(!credentials.cacheable);
+        assert!(credentials.expires_at > Utc::now() + Duration::minutes(55));
+    }
+
+    #[test]
+    fn it_returns_an_error_when_the_helper_fails() {
+        assert_eq!(
+            credential_process::run("exit 3").err(),
+            Some(Error::CredentialProcessFailed("exit status: 3".to_string()))
+        );
+        assert_eq!(
+            credential_process::run("echo not json").err(),
+            Some(Error::CredentialProcessInvalid)
+        );
+        assert_eq!(
+            credential_process::parse(
+                "{\"Version\": 2, \"AccessKeyId\": \"A23\", \"SecretAccessKey\": \"M07\"}"
+            )
+            .err(),
+            Some(Error::CredentialProcessInvalid)
+        );
+        assert_eq!(
+            credential_process::parse(
+                "{\"Version\": 1, \"AccessKeyId\": \"A23\", \"SecretAccessKey\": \"M07\", \
+                 \"Expiration\": \"tomorrow\"}"
+            )
+            .err(),
+            Some(Error::CredentialProcessInvalid)
+        );
+    }
+}
@@ -1,7 +1,40 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Duration, Utc};
+
+use awsudo::profile::StaticCredentials;
+
+/// Lifetime of temporary credentials that do not tell theirs, the default of an STS session.
+const DEFAULT_LIFETIME_SECONDS: i64 = 3600;
+
 #[derive(Debug, PartialEq)]
 pub struct Credentials {
     pub access_key_id: String,
     pub secret_access_key: String,
     pub session_token: String,
+    pub expires_at: DateTime<Utc>,
+    /// Read from the cache, so never written back to it.
     pub cached: bool,
+    /// Whether they may be cached at all, their expiration being known.
+    pub cacheable: bool,
+}
+
+impl Credentials {
+    /// Temporary keys, the ones with a session token, used as they are. Without an expiration,
+    /// they get the default lifetime of a session but are kept out of the cache, as they may
+    /// expire sooner, so they are read again the next time.
+    pub fn temporary(
+        keys: &StaticCredentials,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Option<Credentials> {
+        keys.session_token.as_ref().map(|token| Credentials {
+            access_key_id: keys.access_key_id.clone(),
+            secret_access_key: keys.secret_access_key.clone(),
+            session_token: token.clone(),
+            expires_at: expires_at
+                .unwrap_or_else(|| Utc::now() + Duration::seconds(DEFAULT_LIFETIME_SECONDS)),
+            cached: false,
+            cacheable: expires_at.is_some(),
+        })
+    }
 }
@@ -1,11 +1,147 @@
+#[cfg(unix)]
+extern crate libc;
+
+use awsudo::status;
+use std::env;
+use std::ffi::OsString;
+use std::io;
 use std::process::{Command, Stdio};
 
-pub fn run(command: String) {
-    Command::new("sh")
-        .arg("-c")
-        .arg(command)
+const DEFAULT_SHELL: &str = "sh";
+
+/// Runs the command with the current environment, replacing the awsudo process when possible.
+/// It only returns the exit code awsudo should exit with if the command ran as a child or failed.
+pub fn run(command: Vec<OsString>, shell: bool) -> i32 {
+    execute(command, shell, replace)
+}
+
+/// Runs the command as a child, keeping awsudo alive until it finishes, e.g.: to serve it
+/// credentials. Returns the exit code of the command.
+pub fn run_as_child(command: Vec<OsString>, shell: bool) -> i32 {
+    execute(command, shell, wait)
+}
+
+/// The user's shell, started when awsudo is given no command.
+pub fn user_shell() -> OsString {
+    env::var_os("SHELL").unwrap_or_else(|| OsString::from(DEFAULT_SHELL))
+}
+
+fn execute(command: Vec<OsString>, shell: bool, launch: fn(Command) -> io::Result<i32>) -> i32 {
+    if command.is_empty() {
+        return 0;
+    }
+
+    let mut process = build(command, shell);
+    process
+        .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .output()
-        .expect("Something went wrong");
+        .stderr(Stdio::inherit());
+
+    match launch(process) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("awsudo: the command could not be started: {}", e);
+            match e.kind() {
+                io::ErrorKind::PermissionDenied => status::COMMAND_NOT_EXECUTABLE,
+                _ => status::COMMAND_FAILURE,
+            }
+        }
+    }
+}
+
+fn build(command: Vec<OsString>, shell: bool) -> Command {
+    if shell {
+        let joined = command
+            .iter()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut process = Command::new(user_shell());
+        process.arg("-c").arg(joined);
+        process
+    } else {
+        let mut process = Command::new(&command[0]);
+        process.args(&command[1..]);
+        process
+    }
+}
+
+#[cfg(unix)]
+fn replace(mut process: Command) -> io::Result<i32> {
+    use std::os::unix::process::CommandExt;
+
+    // exec only returns when the command could not be executed
+    Err(process.exec())
+}
+
+#[cfg(not(unix))]
+fn replace(process: Command) -> io::Result<i32> {
+    wait(process)
+}
+
+fn wait(mut process: Command) -> io::Result<i32> {
+    let mut child = process.spawn()?;
+    ignore_interrupts();
+
+    child.wait().map(status::code)
+}
+
+// Ctrl-C is delivered to the whole process group, awsudo must outlive the command it serves
+#[cfg(unix)]
+fn ignore_interrupts() {
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+    }
+}
+
+#[cfg(not(unix))]
+fn ignore_interrupts() {}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::dispatcher;
+    use std::ffi::{OsStr, OsString};
+    use std::process::Command;
+
+    fn args(values: Vec<&str>) -> Vec<OsString> {
+        values.into_iter().map(OsString::from).collect()
+    }
+
+    fn built_args(command: &Command) -> Vec<&OsStr> {
+        command.get_args().collect()
+    }
+
+    #[test]
+    fn it_passes_the_arguments_untouched() {
+        let command = dispatcher::build(
+            args(vec!["aws", "s3", "cp", "my file.txt", "$HOME;ls"]),
+            false,
+        );
+
+        assert_eq!(command.get_program(), "aws");
+        assert_eq!(
+            built_args(&command),
+            vec!["s3", "cp", "my file.txt", "$HOME;ls"]
+        );
+    }
+
+    #[test]
+    fn it_joins_the_arguments_for_the_shell() {
+        let command = dispatcher::build(args(vec!["echo", "$HOME"]), true);
+
+        assert_eq!(command.get_program(), dispatcher::user_shell());
+        assert_eq!(built_args(&command), vec!["-c", "echo $HOME"]);
+    }
+
+    #[test]
+    fn it_returns_success_when_there_is_no_command() {
+        assert_eq!(dispatcher::run(vec![], false), 0);
+    }
+
+    #[test]
+    fn it_returns_the_exit_code_of_a_child_command() {
+        assert_eq!(dispatcher::run_as_child(args(vec!["false"]), false), 1);
+        assert_eq!(dispatcher::run_as_child(args(vec!["exit 3"]), true), 3);
+    }
 }
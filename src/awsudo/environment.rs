@@ -1,26 +1,85 @@
 use awsudo::credentials::Credentials;
+use awsudo::server::Server;
+use awsudo::session::Session;
 use std::env;
 
+pub const SESSION_PROFILE_VARIABLE: &str = "AWSUDO_PROFILE";
+
+pub const CACHE_PASSPHRASE_VARIABLE: &str = "AWSUDO_CACHE_PASSPHRASE";
+
+pub const PROFILE_VARIABLE: &str = "AWS_PROFILE";
+
+pub const CREDENTIALS_VARIABLES: [&str; 3] = [
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+];
+
+/// Keeps the passphrase of the encrypted cache away from the command, once it was read.
+pub fn remove_cache_passphrase() {
+    env::remove_var(CACHE_PASSPHRASE_VARIABLE);
+}
+
 impl Credentials {
     pub fn inject(&self) {
-        env::set_var("AWS_ACCESS_KEY_ID", &self.access_key_id);
-        env::set_var("AWS_SECRET_ACCESS_KEY", &self.secret_access_key);
-        env::set_var("AWS_SESSION_TOKEN", &self.session_token);
+        for (name, value) in self.variables().iter() {
+            env::set_var(name, value);
+        }
+    }
+
+    /// Environment variables holding the credentials, in the order of `CREDENTIALS_VARIABLES`.
+    pub fn variables(&self) -> [(&str, &str); 3] {
+        [
+            (CREDENTIALS_VARIABLES[0], &self.access_key_id),
+            (CREDENTIALS_VARIABLES[1], &self.secret_access_key),
+            (CREDENTIALS_VARIABLES[2], &self.session_token),
+        ]
+    }
+}
+
+impl Server {
+    /// Points the SDKs to the server, removing static credentials that would take precedence,
+    /// and the profile, already assumed by awsudo, which the SDKs would assume again.
+    pub fn inject(&self) {
+        for name in CREDENTIALS_VARIABLES.iter() {
+            env::remove_var(name);
+        }
+        env::remove_var(PROFILE_VARIABLE);
+        env::set_var("AWS_CONTAINER_CREDENTIALS_FULL_URI", &self.url);
+        env::set_var("AWS_CONTAINER_AUTHORIZATION_TOKEN", &self.token);
+    }
+}
+
+impl Session {
+    /// Describes the session to the shell, e.g.: to show the profile in the prompt.
+    pub fn inject(&self) {
+        env::set_var(SESSION_PROFILE_VARIABLE, &self.profile);
+        env::set_var("AWSUDO_EXPIRES_AT", self.expires_at.to_rfc3339());
+        env::set_var("AWSUDO_PROMPT", self.prompt());
     }
 }
 
 #[cfg(test)]
 mod tests {
     use awsudo::credentials::Credentials;
+    use awsudo::environment;
+    use awsudo::server::Server;
     use std::env;
+    use std::sync::Mutex;
+
+    // The tests share the environment of the process, the server removes the credentials
+    static ENV: Mutex<()> = Mutex::new(());
 
     #[test]
     fn it_injects_credential_variable_to_env() {
+        let _env = ENV.lock().unwrap();
         Credentials {
             access_key_id: "m".to_string(),
             secret_access_key: "b".to_string(),
             session_token: "j".to_string(),
+            expires_at: chrono::Utc::now(),
             cached: false,
+            cacheable: true,
         }
         .inject();
 
@@ -28,4 +87,29 @@ mod tests {
         assert_eq!(env::var("AWS_SECRET_ACCESS_KEY"), Ok("b".to_string()));
         assert_eq!(env::var("AWS_SESSION_TOKEN"), Ok("j".to_string()));
     }
+
+    #[test]
+    fn it_points_the_sdks_to_the_server_instead_of_the_profile() {
+        let _env = ENV.lock().unwrap();
+        env::set_var("AWS_PROFILE", "production");
+        Server {
+            url: "http://127.0.0.1:8080/".to_string(),
+            token: "t0k3n".to_string(),
+        }
+        .inject();
+
+        assert!(env::var_os("AWS_PROFILE").is_none());
+        assert_eq!(
+            env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI"),
+            Ok("http://127.0.0.1:8080/".to_string())
+        );
+    }
+
+    #[test]
+    fn it_removes_the_cache_passphrase_from_env() {
+        env::set_var("AWSUDO_CACHE_PASSPHRASE", "correct horse");
+        environment::remove_cache_passphrase();
+
+        assert!(env::var_os("AWSUDO_CACHE_PASSPHRASE").is_none());
+    }
 }
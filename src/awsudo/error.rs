@@ -0,0 +1,361 @@
+extern crate rusoto_core;
+
+use self::rusoto_core::RusotoError;
+
+use awsudo::status;
+use std::error;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    ProfileFileNotFound,
+    ProfileNotFound,
+    ProfileRoleArnNotFound,
+    ProfileSourceNotFound,
+    ProfileSourceCycle,
+    ProfileInvalidDuration,
+    ProfileInvalidStsRegionalEndpoints,
+    ProfileWebIdentityTokenNotFound,
+    ProfileSsoSessionNotFound,
+    ProfileSsoNotFound,
+    ProfileSsoRoleNotFound,
+    CacheNotFound,
+    CacheMissingValues,
+    CacheInvalidDate,
+    CacheExpired,
+    CacheDirNotCreated,
+    CacheDirInsecure,
+    CacheFileNotCreated,
+    CacheNotRemoved,
+    CacheInsecure,
+    CacheMismatch,
+    CacheLockTimeout,
+    CacheLockUnsupported,
+    CacheKeyNotRead,
+    CacheNotDecrypted,
+    MfaNoTerminal,
+    MfaInvalidFormat,
+    MfaRejected(String),
+    Sts { code: String, message: String },
+    StsInvalidExpiration,
+    StsMissingCredentials,
+    ServerNotStarted(String),
+    ShellNested(String),
+    SamlAssertionNotRead,
+    SamlAssertionInvalid,
+    SamlRoleNotFound,
+    SamlRoleNotChosen,
+    SamlAssertionStdinTaken,
+    Sso { code: String, message: String },
+    SsoTokenNotFound(String),
+    SsoLoginExpired,
+    SsoInvalidResponse,
+    SsoCacheDirNotFound,
+    CredentialProcessFailed(String),
+    CredentialProcessInvalid,
+}
+
+impl Error {
+    /// Builds the error from a failed STS call, keeping the code and message sent by AWS.
+    pub fn from_rusoto<E: error::Error + fmt::Debug + 'static>(err: RusotoError<E>) -> Error {
+        let (code, message) = match err {
+            RusotoError::Service(ref e) => (
+                format!("{:?}", e)
+                    .split('(')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+                e.to_string(),
+            ),
+            RusotoError::Unknown(ref response) => {
+                let body = response.body_as_str();
+                (
+                    xml_value(body, "Code").unwrap_or_else(|| response.status.to_string()),
+                    xml_value(body, "Message").unwrap_or_else(|| body.to_string()),
+                )
+            }
+            RusotoError::HttpDispatch(ref e) => ("HttpDispatch".to_string(), e.to_string()),
+            RusotoError::Credentials(ref e) => ("Credentials".to_string(), e.to_string()),
+            RusotoError::Validation(ref m) => ("Validation".to_string(), m.to_string()),
+            RusotoError::ParseError(ref m) => ("ParseError".to_string(), m.to_string()),
+        };
+
+        if code == "AccessDenied" && message.contains("MultiFactorAuthentication") {
+            Error::MfaRejected(message)
+        } else {
+            Error::Sts { code, message }
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::ProfileFileNotFound
+            | Error::ProfileNotFound
+            | Error::ProfileRoleArnNotFound
+            | Error::ProfileSourceNotFound
+            | Error::ProfileSourceCycle
+            | Error::ProfileInvalidDuration
+            | Error::ProfileInvalidStsRegionalEndpoints
+            | Error::ProfileWebIdentityTokenNotFound
+            | Error::ProfileSsoSessionNotFound
+            | Error::ProfileSsoNotFound
+            | Error::ProfileSsoRoleNotFound => status::PROFILE_FAILURE,
+            Error::CacheNotFound
+            | Error::CacheMissingValues
+            | Error::CacheInvalidDate
+            | Error::CacheExpired
+            | Error::CacheDirNotCreated
+            | Error::CacheDirInsecure
+            | Error::CacheFileNotCreated
+            | Error::CacheNotRemoved
+            | Error::CacheInsecure
+            | Error::CacheMismatch
+            | Error::CacheLockTimeout
+            | Error::CacheLockUnsupported
+            | Error::CacheKeyNotRead
+            | Error::CacheNotDecrypted => status::CACHE_FAILURE,
+            Error::MfaNoTerminal | Error::MfaInvalidFormat | Error::MfaRejected(_) => {
+                status::MFA_FAILURE
+            }
+            Error::Sts { .. } | Error::StsInvalidExpiration | Error::StsMissingCredentials => {
+                status::STS_FAILURE
+            }
+            Error::ServerNotStarted(_) => status::SERVER_FAILURE,
+            Error::ShellNested(_) => status::SHELL_NESTED,
+            Error::SamlAssertionNotRead
+            | Error::SamlAssertionInvalid
+            | Error::SamlRoleNotFound
+            | Error::SamlRoleNotChosen
+            | Error::SamlAssertionStdinTaken => status::SAML_FAILURE,
+            Error::Sso { .. }
+            | Error::SsoTokenNotFound(_)
+            | Error::SsoLoginExpired
+            | Error::SsoInvalidResponse
+            | Error::SsoCacheDirNotFound => status::SSO_FAILURE,
+            Error::CredentialProcessFailed(_) | Error::CredentialProcessInvalid => {
+                status::CREDENTIAL_PROCESS_FAILURE
+            }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ProfileFileNotFound => write!(f, "Profile file not found"),
+            Error::ProfileNotFound => write!(f, "Profile not found"),
+            Error::ProfileRoleArnNotFound => {
+                write!(f, "Profile role_arn or static credentials not found")
+            }
+            Error::ProfileSourceNotFound => write!(f, "Profile source_profile not found"),
+            Error::ProfileSourceCycle => write!(f, "Profile source_profile chain has a cycle"),
+            Error::ProfileInvalidDuration => write!(f, "Profile duration_seconds is not a number"),
+            Error::ProfileInvalidStsRegionalEndpoints => write!(
+                f,
+                "Profile sts_regional_endpoints must be legacy or regional"
+            ),
+            Error::ProfileWebIdentityTokenNotFound => {
+                write!(f, "Profile web_identity_token_file cannot be read")
+            }
+            Error::ProfileSsoSessionNotFound => write!(
+                f,
+                "Profile sso-session section not found or missing sso_start_url or sso_region"
+            ),
+            Error::ProfileSsoNotFound => {
+                write!(f, "Profile sso_session or sso_start_url not found")
+            }
+            Error::ProfileSsoRoleNotFound => {
+                write!(f, "Profile sso_account_id or sso_role_name not found")
+            }
+            Error::CacheNotFound => write!(f, "Cache file is not present or not valid"),
+            Error::CacheMissingValues => write!(f, "Cache file is missing required values"),
+            Error::CacheInvalidDate => write!(f, "Cache file does not have a valid date"),
+            Error::CacheExpired => write!(f, "Cache file is expired"),
+            Error::CacheDirNotCreated => {
+                write!(f, "Failed to persist cache: dir cannot be created")
+            }
+            Error::CacheDirInsecure => write!(
+                f,
+                "Cache dir is not owned by the current user or is writable by others"
+            ),
+            Error::CacheFileNotCreated => {
+                write!(f, "Failed to persist cache: file cannot be created")
+            }
+            Error::CacheNotRemoved => write!(f, "Cache file cannot be removed"),
+            Error::CacheLockTimeout => {
+                write!(f, "Cache lock is held by another process for too long")
+            }
+            Error::CacheLockUnsupported => {
+                write!(f, "Cache lock is not supported by the cache dir")
+            }
+            Error::CacheMismatch => {
+                write!(f, "Cache file was requested for different profile settings")
+            }
+            Error::CacheInsecure => write!(
+                f,
+                "Cache file is not owned by the current user or is readable by others"
+            ),
+            Error::CacheKeyNotRead => {
+                write!(
+                    f,
+                    "Cache passphrase could not be read from the key file or terminal"
+                )
+            }
+            Error::CacheNotDecrypted => write!(
+                f,
+                "Cache file cannot be decrypted: wrong passphrase or tampered file"
+            ),
+            Error::MfaNoTerminal => write!(f, "MFA token could not be read: no terminal available"),
+            Error::MfaInvalidFormat => write!(f, "MFA token must be six digits"),
+            Error::MfaRejected(ref message) => write!(f, "MFA token was rejected: {}", message),
+            Error::Sts {
+                ref code,
+                ref message,
+            } => write!(f, "Request to AWS failed: {}: {}", code, message),
+            Error::StsInvalidExpiration => {
+                write!(f, "Request to AWS returned an invalid expiration")
+            }
+            Error::StsMissingCredentials => write!(f, "Request to AWS returned no credentials"),
+            Error::ServerNotStarted(ref message) => {
+                write!(f, "Credentials server could not be started: {}", message)
+            }
+            Error::ShellNested(ref profile) => write!(
+                f,
+                "Already in an awsudo shell for {}, use --nest to start another one",
+                profile
+            ),
+            Error::SamlAssertionNotRead => write!(f, "SAML assertion could not be read"),
+            Error::SamlAssertionInvalid => {
+                write!(f, "SAML assertion is not valid or grants no role")
+            }
+            Error::SamlRoleNotFound => {
+                write!(f, "SAML assertion does not grant the role of the profile")
+            }
+            Error::SamlRoleNotChosen => write!(f, "SAML role was not chosen"),
+            Error::SamlAssertionStdinTaken => write!(
+                f,
+                "SAML assertion cannot be read from stdin with a command or --credentials-server, stdin is left to the command"
+            ),
+            Error::Sso {
+                ref code,
+                ref message,
+            } => write!(
+                f,
+                "Request to IAM Identity Center failed: {}: {}",
+                code, message
+            ),
+            Error::SsoTokenNotFound(ref session) => write!(
+                f,
+                "SSO token of {} is missing or expired, please sign in with awsudo login",
+                session
+            ),
+            Error::SsoLoginExpired => write!(f, "SSO login was not confirmed in time"),
+            Error::SsoInvalidResponse => {
+                write!(
+                    f,
+                    "Request to IAM Identity Center returned an invalid response"
+                )
+            }
+            Error::SsoCacheDirNotFound => write!(
+                f,
+                "SSO token cache dir cannot be found without a home directory"
+            ),
+            Error::CredentialProcessFailed(ref status) => {
+                write!(f, "Profile credential_process failed: {}", status)
+            }
+            Error::CredentialProcessInvalid => {
+                write!(f, "Profile credential_process printed no valid credentials")
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Reads the text of the first `tag` element of an STS response.
+pub fn xml_value(body: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&close)? + start;
+
+    Some(body[start..end].trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::error::rusoto_core::request::{BufferedHttpResponse, Headers};
+    use awsudo::error::rusoto_core::RusotoError;
+    use awsudo::error::Error;
+    use awsudo::status;
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct ServiceError;
+
+    impl fmt::Display for ServiceError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "unused")
+        }
+    }
+
+    impl ::std::error::Error for ServiceError {}
+
+    fn unknown(body: &str) -> RusotoError<ServiceError> {
+        RusotoError::Unknown(BufferedHttpResponse {
+            status: Default::default(),
+            body: body.as_bytes().to_vec(),
+            headers: Headers::new(vec![]),
+        })
+    }
+
+    #[test]
+    fn it_keeps_the_aws_error_code_and_message() {
+        let err = Error::from_rusoto(unknown(
+            "<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code>\
+             <Message>User: jeff is not authorized to perform: sts:AssumeRole</Message>\
+             </Error></ErrorResponse>",
+        ));
+
+        assert_eq!(
+            err,
+            Error::Sts {
+                code: "AccessDenied".to_string(),
+                message: "User: jeff is not authorized to perform: sts:AssumeRole".to_string(),
+            }
+        );
+        assert_eq!(err.exit_code(), status::STS_FAILURE);
+        assert_eq!(
+            err.to_string(),
+            "Request to AWS failed: AccessDenied: User: jeff is not authorized to perform: sts:AssumeRole"
+        );
+    }
+
+    #[test]
+    fn it_detects_rejected_mfa_tokens() {
+        let err = Error::from_rusoto(unknown(
+            "<ErrorResponse><Error><Code>AccessDenied</Code>\
+             <Message>MultiFactorAuthentication failed with invalid MFA one time pass code.</Message>\
+             </Error></ErrorResponse>",
+        ));
+
+        assert_eq!(
+            err,
+            Error::MfaRejected(
+                "MultiFactorAuthentication failed with invalid MFA one time pass code.".to_string()
+            )
+        );
+        assert_eq!(err.exit_code(), status::MFA_FAILURE);
+    }
+
+    #[test]
+    fn it_falls_back_to_the_raw_response() {
+        assert_eq!(
+            Error::from_rusoto(unknown("Service Unavailable")),
+            Error::Sts {
+                code: "200 OK".to_string(),
+                message: "Service Unavailable".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,181 @@
+extern crate serde_json;
+
+use self::serde_json::{Map, Value};
+
+use awsudo::credentials::Credentials;
+use awsudo::environment::CREDENTIALS_VARIABLES;
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Syntax of the variables printed by `--export`, so they can be evaluated by a shell or
+/// loaded from a file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+    Dotenv,
+    Json,
+}
+
+impl Syntax {
+    /// Picks the syntax of the current `$SHELL`, falling back to bash.
+    pub fn detect() -> Syntax {
+        env::var_os("SHELL")
+            .and_then(|shell| {
+                Path::new(&shell)
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.parse::<Syntax>().ok())
+            })
+            .unwrap_or(Syntax::Bash)
+    }
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Syntax, String> {
+        match name {
+            "bash" | "sh" => Ok(Syntax::Bash),
+            "zsh" => Ok(Syntax::Zsh),
+            "fish" => Ok(Syntax::Fish),
+            "powershell" | "pwsh" => Ok(Syntax::PowerShell),
+            "dotenv" => Ok(Syntax::Dotenv),
+            "json" => Ok(Syntax::Json),
+            _ => Err(format!("Unknown export syntax: {}", name)),
+        }
+    }
+}
+
+impl Credentials {
+    /// Prints the variables `inject` would set, in the given syntax.
+    pub fn export(&self, syntax: Syntax) -> String {
+        match syntax {
+            Syntax::Json => {
+                let variables = self
+                    .variables()
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), Value::from(*value)))
+                    .collect::<Map<String, Value>>();
+
+                Value::Object(variables).to_string()
+            }
+            _ => self
+                .variables()
+                .iter()
+                .map(|(name, value)| set(syntax, name, value))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// Prints the statements removing the variables set by `Credentials::export`.
+pub fn clear(syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Json => {
+            let variables = CREDENTIALS_VARIABLES
+                .iter()
+                .map(|name| (name.to_string(), Value::Null))
+                .collect::<Map<String, Value>>();
+
+            Value::Object(variables).to_string()
+        }
+        _ => CREDENTIALS_VARIABLES
+            .iter()
+            .map(|name| unset(syntax, name))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn set(syntax: Syntax, name: &str, value: &str) -> String {
+    match syntax {
+        Syntax::Bash | Syntax::Zsh => format!("export {}='{}'", name, value.replace('\'', "'\\''")),
+        Syntax::Fish => format!(
+            "set -gx {} '{}'",
+            name,
+            value.replace('\\', "\\\\").replace('\'', "\\'")
+        ),
+        Syntax::PowerShell => format!("$Env:{} = '{}'", name, value.replace('\'', "''")),
+        Syntax::Dotenv | Syntax::Json => format!("{}={}", name, value),
+    }
+}
+
+fn unset(syntax: Syntax, name: &str) -> String {
+    match syntax {
+        Syntax::Bash | Syntax::Zsh => format!("unset {}", name),
+        Syntax::Fish => format!("set -e {}", name),
+        Syntax::PowerShell => format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name),
+        Syntax::Dotenv | Syntax::Json => format!("{}=", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::credentials::Credentials;
+    use awsudo::export;
+    use awsudo::export::Syntax;
+
+    fn credentials() -> Credentials {
+        Credentials {
+            access_key_id: "A23".to_string(),
+            secret_access_key: "M0'7".to_string(),
+            session_token: "B03".to_string(),
+            expires_at: chrono::Utc::now(),
+            cached: false,
+            cacheable: true,
+        }
+    }
+
+    #[test]
+    fn it_parses_the_syntax_names() {
+        assert_eq!("zsh".parse::<Syntax>(), Ok(Syntax::Zsh));
+        assert_eq!("pwsh".parse::<Syntax>(), Ok(Syntax::PowerShell));
+        assert!("cmd".parse::<Syntax>().is_err());
+    }
+
+    #[test]
+    fn it_exports_quoted_variables_for_bash() {
+        assert_eq!(
+            credentials().export(Syntax::Bash),
+            "export AWS_ACCESS_KEY_ID='A23'\n\
+             export AWS_SECRET_ACCESS_KEY='M0'\\''7'\n\
+             export AWS_SESSION_TOKEN='B03'"
+        );
+    }
+
+    #[test]
+    fn it_exports_quoted_variables_for_fish_and_powershell() {
+        assert!(credentials()
+            .export(Syntax::Fish)
+            .contains("set -gx AWS_SECRET_ACCESS_KEY 'M0\\'7'"));
+        assert!(credentials()
+            .export(Syntax::PowerShell)
+            .contains("$Env:AWS_SECRET_ACCESS_KEY = 'M0''7'"));
+    }
+
+    #[test]
+    fn it_exports_dotenv_and_json() {
+        assert_eq!(
+            credentials().export(Syntax::Dotenv),
+            "AWS_ACCESS_KEY_ID=A23\nAWS_SECRET_ACCESS_KEY=M0'7\nAWS_SESSION_TOKEN=B03"
+        );
+        assert_eq!(
+            credentials().export(Syntax::Json),
+            "{\"AWS_ACCESS_KEY_ID\":\"A23\",\"AWS_SECRET_ACCESS_KEY\":\"M0'7\",\"AWS_SESSION_TOKEN\":\"B03\"}"
+        );
+    }
+
+    #[test]
+    fn it_clears_the_variables() {
+        assert_eq!(
+            export::clear(Syntax::Bash),
+            "unset AWS_ACCESS_KEY_ID\nunset AWS_SECRET_ACCESS_KEY\nunset AWS_SESSION_TOKEN"
+        );
+        assert!(export::clear(Syntax::Fish).starts_with("set -e AWS_ACCESS_KEY_ID"));
+    }
+}
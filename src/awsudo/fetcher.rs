@@ -1,5 +1,6 @@
 use awsudo::credentials::Credentials;
+use awsudo::error::Error;
 
 pub trait Fetcher {
-    fn fetch(&self) -> Result<Credentials, &'static str>;
+    fn fetch(&self) -> Result<Credentials, Error>;
 }
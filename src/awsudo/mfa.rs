@@ -0,0 +1,46 @@
+extern crate rpassword;
+
+use awsudo::error::Error;
+
+const MFA_TOKEN_LENGTH: usize = 6;
+
+/// Prompts for the MFA token on the controlling terminal, with echo disabled, leaving
+/// stdin and stdout to the command.
+pub fn collect(mfa_serial: String) -> Result<String, Error> {
+    let prompt = format!("Please type your MFA token for {}: ", mfa_serial);
+
+    match rpassword::read_password_from_tty(Some(&prompt)) {
+        Ok(input) => validate(&input),
+        Err(_) => Err(Error::MfaNoTerminal),
+    }
+}
+
+fn validate(input: &str) -> Result<String, Error> {
+    let token = input.trim();
+
+    if token.len() == MFA_TOKEN_LENGTH && token.chars().all(|c| c.is_ascii_digit()) {
+        Ok(token.to_string())
+    } else {
+        Err(Error::MfaInvalidFormat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::error::Error;
+    use awsudo::mfa;
+
+    #[test]
+    fn it_accepts_six_digits() {
+        assert_eq!(mfa::validate("123456"), Ok(String::from("123456")));
+        assert_eq!(mfa::validate(" 012345\n"), Ok(String::from("012345")));
+    }
+
+    #[test]
+    fn it_rejects_anything_else() {
+        assert_eq!(mfa::validate(""), Err(Error::MfaInvalidFormat));
+        assert_eq!(mfa::validate("12345"), Err(Error::MfaInvalidFormat));
+        assert_eq!(mfa::validate("1234567"), Err(Error::MfaInvalidFormat));
+        assert_eq!(mfa::validate("12e456"), Err(Error::MfaInvalidFormat));
+    }
+}
@@ -1,52 +1,327 @@
 extern crate ini;
 
+use self::ini::ini::Properties;
 use self::ini::Ini;
+
+use awsudo::error::Error;
 use std::path::PathBuf;
 
-#[derive(Debug)]
+const DEFAULT_PROFILE: &str = "default";
+const SSO_SESSION_SECTION: &str = "sso-session";
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Profile {
-    pub role_arn: String,
-    pub region: String,
+    pub role_arn: Option<String>,
+    pub region: Option<String>,
     pub mfa_serial: Option<String>,
+    pub source_profile: Option<String>,
+    pub duration_seconds: Option<i64>,
+    pub external_id: Option<String>,
+    pub role_session_name: Option<String>,
+    pub sts_regional_endpoints: Option<String>,
+    pub sts_endpoint_url: Option<String>,
+    pub static_credentials: Option<StaticCredentials>,
+    pub web_identity_token_file: Option<String>,
+    pub saml_assertion: Option<SamlAssertion>,
+    pub saml_principal_arn: Option<String>,
+    pub sso_session: Option<SsoSession>,
+    pub sso_account_id: Option<String>,
+    pub sso_role_name: Option<String>,
+    pub credential_process: Option<String>,
 }
 
-impl PartialEq for Profile {
-    fn eq(&self, other: &Profile) -> bool {
-        self.role_arn == other.role_arn
-            && self.region == other.region
-            && self.mfa_serial == other.mfa_serial
-    }
+/// Long-term keys of a profile, used to call STS instead of the ambient credentials.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Where a SAML profile reads its base64 assertion from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SamlAssertion {
+    File(String),
+    Stdin,
+    Command(String),
+}
+
+/// An IAM Identity Center sign-in, from an `[sso-session]` section or from the legacy
+/// `sso_start_url` and `sso_region` settings of the profile, which have no name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SsoSession {
+    pub name: Option<String>,
+    pub start_url: String,
+    pub region: String,
+    pub registration_scopes: Vec<String>,
 }
 
 impl Profile {
-    pub fn load_from(file_path: PathBuf, user: String) -> Result<Profile, &'static str> {
-        let profile = format!("profile {}", user);
+    /// Loads the profile from the config file, taking its static keys from the shared
+    /// credentials file, or from the config file itself.
+    pub fn load_from(
+        file_path: PathBuf,
+        credentials_path: PathBuf,
+        user: String,
+    ) -> Result<Profile, Error> {
+        let profile = if user == DEFAULT_PROFILE {
+            user.to_owned()
+        } else {
+            format!("profile {}", user)
+        };
+        let credentials = Ini::load_from_file(&credentials_path).ok();
+        let credentials_section = credentials
+            .as_ref()
+            .and_then(|ini| ini.section(Some(user.to_owned())));
+
+        let config = match Ini::load_from_file(&file_path) {
+            Ok(ini) => ini,
+            Err(_) if credentials_section.is_some() => Ini::new(),
+            Err(_) => return Err(Error::ProfileFileNotFound),
+        };
+        let empty = Properties::new();
+        let s = match (
+            config.section(Some(profile.to_owned())),
+            credentials_section,
+        ) {
+            (Some(s), _) => s,
+            (None, Some(_)) => &empty,
+            (None, None) => return Err(Error::ProfileNotFound),
+        };
+
+        let static_credentials = credentials_section
+            .and_then(static_credentials)
+            .or_else(|| static_credentials(s));
+        let saml_assertion = match (
+            s.get("saml_assertion_file").map(|s| s.as_str()),
+            s.get("saml_assertion_command"),
+        ) {
+            (Some("-"), _) => Some(SamlAssertion::Stdin),
+            (Some(file), _) => Some(SamlAssertion::File(file.to_string())),
+            (None, Some(command)) => Some(SamlAssertion::Command(command.to_string())),
+            (None, None) => None,
+        };
+        let sso_session = match (
+            s.get("sso_session"),
+            s.get("sso_start_url"),
+            s.get("sso_region"),
+        ) {
+            (Some(name), _, _) => Some(sso_session(&config, name)?),
+            (None, Some(start_url), Some(region)) => Some(SsoSession {
+                name: None,
+                start_url: start_url.to_string(),
+                region: region.to_string(),
+                registration_scopes: vec![],
+            }),
+            _ => None,
+        };
+        let credential_process = s.get("credential_process").map(|s| s.to_string());
+
+        match (
+            s.get("role_arn"),
+            s.get("mfa_serial"),
+            s.get("region"),
+            s.get("duration_seconds").map(|d| d.parse::<i64>()),
+        ) {
+            (None, _, _, _)
+                if static_credentials.is_none()
+                    && saml_assertion.is_none()
+                    && sso_session.is_none()
+                    && credential_process.is_none() =>
+            {
+                Err(Error::ProfileRoleArnNotFound)
+            }
+            (_, _, _, Some(Err(_))) => Err(Error::ProfileInvalidDuration),
+            (role_arn, mfa, region, duration) => Ok(Profile {
+                role_arn: role_arn.map(|s| s.to_string()),
+                mfa_serial: mfa.map(|s| s.to_string()),
+                region: region.map(|s| s.to_string()),
+                source_profile: s.get("source_profile").map(|s| s.to_string()),
+                duration_seconds: duration.and_then(|d| d.ok()),
+                external_id: s.get("external_id").map(|s| s.to_string()),
+                role_session_name: s.get("role_session_name").map(|s| s.to_string()),
+                sts_regional_endpoints: s.get("sts_regional_endpoints").map(|s| s.to_string()),
+                sts_endpoint_url: s
+                    .get("sts_endpoint_url")
+                    .or_else(|| s.get("endpoint_url"))
+                    .map(|s| s.to_string()),
+                static_credentials,
+                web_identity_token_file: s.get("web_identity_token_file").map(|s| s.to_string()),
+                saml_assertion,
+                saml_principal_arn: s.get("saml_principal_arn").map(|s| s.to_string()),
+                sso_session,
+                sso_account_id: s.get("sso_account_id").map(|s| s.to_string()),
+                sso_role_name: s.get("sso_role_name").map(|s| s.to_string()),
+                credential_process,
+            }),
+        }
+    }
+
+    /// Loads an `[sso-session]` section by name, e.g.: to sign in without going through a
+    /// profile.
+    pub fn load_sso_session(file_path: PathBuf, name: &str) -> Result<SsoSession, Error> {
         match Ini::load_from_file(&file_path) {
-            Err(_) => Err("Profile file not found"),
-            Ok(ini) => match ini.section(Some(profile.to_owned())) {
-                Some(s) => match (s.get("role_arn"), s.get("mfa_serial"), s.get("region")) {
-                    (None, _, _) => Err("Profile role_arn not found"),
-                    (Some(role_arn), mfa, region) => Ok(Profile {
-                        role_arn: role_arn.to_string(),
-                        mfa_serial: match mfa {
-                            None => None,
-                            Some(s) => Some(s.to_string()),
-                        },
-                        region: match region {
-                            Some(r) => r.to_string(),
-                            None => "eu-central-1".to_string(),
-                        },
-                    }),
-                },
-                None => Err("Profile not found"),
-            },
+            Ok(config) => sso_session(&config, name),
+            Err(_) => Err(Error::ProfileFileNotFound),
+        }
+    }
+
+    /// The role of the web identity given by the environment, e.g.: an OIDC token of a CI
+    /// runner, assumed with the token read from `token_file`.
+    pub fn web_identity(
+        role_arn: String,
+        token_file: String,
+        role_session_name: Option<String>,
+    ) -> Profile {
+        Profile {
+            role_arn: Some(role_arn),
+            role_session_name,
+            web_identity_token_file: Some(token_file),
+            ..Default::default()
+        }
+    }
+
+    /// Reads a setting from the `[default]` section, the last fallback for profile settings.
+    pub fn default_setting(file_path: PathBuf, key: &str) -> Option<String> {
+        match Ini::load_from_file(&file_path) {
+            Err(_) => None,
+            Ok(ini) => ini
+                .section(Some(DEFAULT_PROFILE.to_owned()))
+                .and_then(|s| s.get(key))
+                .map(|s| s.to_string()),
+        }
+    }
+
+    /// Loads the given profile together with every profile it depends on through
+    /// `source_profile`, ordered from the first role to assume to the requested one.
+    pub fn load_chain(
+        file_path: PathBuf,
+        credentials_path: PathBuf,
+        user: String,
+    ) -> Result<Vec<Profile>, Error> {
+        let mut visited: Vec<String> = vec![];
+        let mut chain: Vec<Profile> = vec![];
+        let mut next = Some(user);
+
+        while let Some(name) = next {
+            if visited.contains(&name) {
+                return Err(Error::ProfileSourceCycle);
+            }
+
+            let profile =
+                match Profile::load_from(file_path.clone(), credentials_path.clone(), name.clone())
+                {
+                    Err(Error::ProfileNotFound) if !visited.is_empty() => {
+                        return Err(Error::ProfileSourceNotFound)
+                    }
+                    result => result?,
+                };
+
+            next = profile.source_profile.clone();
+            visited.push(name);
+            chain.push(profile);
+        }
+
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Uses the web identity of the environment as the credentials the chain starts from,
+    /// like the ambient credentials, so the same profile works where only a web identity
+    /// token is available. Chains starting from credentials of their own are kept as they are.
+    /// Without a config file, the web identity role is assumed by itself, but a profile missing
+    /// from the config file, e.g.: a typo, is still an error.
+    pub fn with_web_identity(
+        chain: Result<Vec<Profile>, Error>,
+        web_identity: Option<Profile>,
+    ) -> Result<Vec<Profile>, Error> {
+        let web_identity = match web_identity {
+            Some(web_identity) => web_identity,
+            None => return chain,
+        };
+
+        match chain {
+            Err(Error::ProfileFileNotFound) => Ok(vec![web_identity]),
+            Err(e) => Err(e),
+            Ok(mut chain) => {
+                let first = &mut chain[0];
+                if first.static_credentials.is_some()
+                    || first.web_identity_token_file.is_some()
+                    || first.credential_process.is_some()
+                    || first.saml_assertion.is_some()
+                    || first.sso_session.is_some()
+                {
+                    // The chain has credentials of its own
+                } else if first.role_arn == web_identity.role_arn {
+                    first.web_identity_token_file = web_identity.web_identity_token_file;
+                } else {
+                    chain.insert(0, web_identity);
+                }
+
+                Ok(chain)
+            }
+        }
+    }
+
+    /// Replaces the session settings of the profile with the ones given on the command line.
+    pub fn override_with(
+        &mut self,
+        duration_seconds: Option<i64>,
+        role_session_name: Option<String>,
+    ) {
+        if duration_seconds.is_some() {
+            self.duration_seconds = duration_seconds;
+        }
+
+        if role_session_name.is_some() {
+            self.role_session_name = role_session_name;
         }
     }
 }
 
+fn static_credentials(section: &Properties) -> Option<StaticCredentials> {
+    match (
+        section.get("aws_access_key_id"),
+        section.get("aws_secret_access_key"),
+    ) {
+        (Some(access_key_id), Some(secret_access_key)) => Some(StaticCredentials {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: section.get("aws_session_token").map(|s| s.to_string()),
+        }),
+        _ => None,
+    }
+}
+
+fn sso_session(config: &Ini, name: &str) -> Result<SsoSession, Error> {
+    let section = config
+        .section(Some(format!("{} {}", SSO_SESSION_SECTION, name)))
+        .ok_or(Error::ProfileSsoSessionNotFound)?;
+
+    match (section.get("sso_start_url"), section.get("sso_region")) {
+        (Some(start_url), Some(region)) => Ok(SsoSession {
+            name: Some(name.to_string()),
+            start_url: start_url.to_string(),
+            region: region.to_string(),
+            registration_scopes: section
+                .get("sso_registration_scopes")
+                .map(|scopes| {
+                    scopes
+                        .split(',')
+                        .map(|scope| scope.trim().to_string())
+                        .filter(|scope| !scope.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }),
+        _ => Err(Error::ProfileSsoSessionNotFound),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use awsudo::profile::Profile;
+    use awsudo::error::Error;
+    use awsudo::profile::{Profile, SamlAssertion, SsoSession, StaticCredentials};
     use std::path::PathBuf;
 
     fn fixtures_path(file: &str) -> PathBuf {
@@ -56,45 +331,70 @@ mod tests {
         p
     }
 
+    fn credentials_path(file: &str) -> PathBuf {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures/credentials/");
+        p.push(file);
+        p
+    }
+
     #[test]
     fn it_returns_an_error_when_file_is_not_found() {
-        let r = Profile::load_from(fixtures_path("unexistent"), "staging".to_string());
+        let r = Profile::load_from(
+            fixtures_path("unexistent"),
+            credentials_path("none"),
+            "staging".to_string(),
+        );
 
-        assert_eq!(r, Err("Profile file not found"));
+        assert_eq!(r, Err(Error::ProfileFileNotFound));
     }
 
     #[test]
     fn it_returns_an_error_when_section_is_not_found() {
-        let r = Profile::load_from(fixtures_path("multi_profile"), "staging".to_string());
+        let r = Profile::load_from(
+            fixtures_path("multi_profile"),
+            credentials_path("none"),
+            "staging".to_string(),
+        );
 
-        assert_eq!(r, Err("Profile not found"));
+        assert_eq!(r, Err(Error::ProfileNotFound));
     }
 
     #[test]
     fn it_returns_an_error_when_arn_is_not_found() {
-        let r = Profile::load_from(fixtures_path("missing_values"), "missing_arn".to_string());
+        let r = Profile::load_from(
+            fixtures_path("missing_values"),
+            credentials_path("none"),
+            "missing_arn".to_string(),
+        );
 
-        assert_eq!(r, Err("Profile role_arn not found"));
+        assert_eq!(r, Err(Error::ProfileRoleArnNotFound));
     }
 
     #[test]
     fn it_returns_none_when_mfa_is_not_found() {
-        let r = Profile::load_from(fixtures_path("missing_values"), "missing_mfa".to_string());
+        let r = Profile::load_from(
+            fixtures_path("missing_values"),
+            credentials_path("none"),
+            "missing_mfa".to_string(),
+        );
 
         assert_eq!(
             r,
             Ok(Profile {
                 mfa_serial: None,
-                role_arn: String::from("example-arn"),
-                region: String::from("us-east-1"),
+                role_arn: Some(String::from("example-arn")),
+                region: Some(String::from("us-east-1")),
+                ..Default::default()
             },)
         );
     }
 
     #[test]
-    fn it_returns_default_when_region_is_not_found() {
+    fn it_returns_none_when_region_is_not_found() {
         let r = Profile::load_from(
             fixtures_path("missing_values"),
+            credentials_path("none"),
             "missing_region".to_string(),
         );
 
@@ -102,23 +402,472 @@ mod tests {
             r,
             Ok(Profile {
                 mfa_serial: Some(String::from("example-mfa")),
-                role_arn: String::from("example-arn"),
-                region: String::from("eu-central-1"),
+                role_arn: Some(String::from("example-arn")),
+                region: None,
+                ..Default::default()
             },)
         );
     }
 
     #[test]
     fn it_returns_mfa_when_mfa_is_found() {
-        let r = Profile::load_from(fixtures_path("multi_profile"), "complete".to_string());
+        let r = Profile::load_from(
+            fixtures_path("multi_profile"),
+            credentials_path("none"),
+            "complete".to_string(),
+        );
 
         assert_eq!(
             r,
             Ok(Profile {
                 mfa_serial: Some(String::from("example-mfa")),
-                role_arn: String::from("example-arn"),
-                region: String::from("us-east-1"),
+                role_arn: Some(String::from("example-arn")),
+                region: Some(String::from("us-east-1")),
+                ..Default::default()
             },)
         );
     }
+
+    #[test]
+    fn it_returns_a_single_profile_chain_when_there_is_no_source_profile() {
+        let r = Profile::load_chain(
+            fixtures_path("multi_profile"),
+            credentials_path("none"),
+            "complete".to_string(),
+        );
+
+        assert_eq!(r.map(|chain| chain.len()), Ok(1));
+    }
+
+    #[test]
+    fn it_returns_the_chain_ordered_from_the_source_profile() {
+        let r = Profile::load_chain(
+            fixtures_path("chained"),
+            credentials_path("none"),
+            "prod".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            r.iter()
+                .map(|p| p.role_arn.as_deref().unwrap())
+                .collect::<Vec<&str>>(),
+            vec!["base-arn", "hub-arn", "prod-arn"]
+        );
+        assert_eq!(r[0].mfa_serial, Some(String::from("base-mfa")));
+        assert_eq!(r[1].mfa_serial, None);
+        assert_eq!(r[2].mfa_serial, None);
+    }
+
+    #[test]
+    fn it_returns_an_error_when_the_source_profile_is_not_found() {
+        let r = Profile::load_chain(
+            fixtures_path("chained"),
+            credentials_path("none"),
+            "orphan".to_string(),
+        );
+
+        assert_eq!(r, Err(Error::ProfileSourceNotFound));
+    }
+
+    #[test]
+    fn it_returns_an_error_when_the_chain_has_a_cycle() {
+        let r = Profile::load_chain(
+            fixtures_path("chained"),
+            credentials_path("none"),
+            "cycle_a".to_string(),
+        );
+
+        assert_eq!(r, Err(Error::ProfileSourceCycle));
+    }
+
+    #[test]
+    fn it_returns_an_error_when_the_profile_is_its_own_source() {
+        let r = Profile::load_chain(
+            fixtures_path("chained"),
+            credentials_path("none"),
+            "narcissus".to_string(),
+        );
+
+        assert_eq!(r, Err(Error::ProfileSourceCycle));
+    }
+
+    #[test]
+    fn it_returns_session_settings_when_they_are_found() {
+        let r = Profile::load_from(
+            fixtures_path("session"),
+            credentials_path("none"),
+            "audit".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(r.duration_seconds, Some(900));
+        assert_eq!(r.external_id, Some(String::from("example-external-id")));
+        assert_eq!(r.role_session_name, Some(String::from("jeff")));
+    }
+
+    #[test]
+    fn it_returns_an_error_when_duration_seconds_is_not_a_number() {
+        let r = Profile::load_from(
+            fixtures_path("session"),
+            credentials_path("none"),
+            "invalid_duration".to_string(),
+        );
+
+        assert_eq!(r, Err(Error::ProfileInvalidDuration));
+    }
+
+    #[test]
+    fn it_overrides_session_settings() {
+        let mut r = Profile::load_from(
+            fixtures_path("session"),
+            credentials_path("none"),
+            "audit".to_string(),
+        )
+        .unwrap();
+
+        r.override_with(Some(3600), None);
+
+        assert_eq!(r.duration_seconds, Some(3600));
+        assert_eq!(r.role_session_name, Some(String::from("jeff")));
+
+        r.override_with(None, Some(String::from("bezos")));
+
+        assert_eq!(r.duration_seconds, Some(3600));
+        assert_eq!(r.role_session_name, Some(String::from("bezos")));
+    }
+
+    #[test]
+    fn it_returns_settings_from_the_default_section() {
+        assert_eq!(
+            Profile::default_setting(fixtures_path("multi_profile"), "region"),
+            Some(String::from("ap-southeast-2"))
+        );
+        assert_eq!(
+            Profile::default_setting(fixtures_path("multi_profile"), "sts_regional_endpoints"),
+            Some(String::from("legacy"))
+        );
+        assert_eq!(
+            Profile::default_setting(fixtures_path("missing_values"), "region"),
+            None
+        );
+    }
+
+    #[test]
+    fn it_returns_the_sts_endpoint_url() {
+        let r = Profile::load_from(
+            fixtures_path("endpoints"),
+            credentials_path("none"),
+            "sts_specific".to_string(),
+        );
+
+        assert_eq!(
+            r.unwrap().sts_endpoint_url,
+            Some(String::from("http://localhost:4566"))
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_the_generic_endpoint_url() {
+        let r = Profile::load_from(
+            fixtures_path("endpoints"),
+            credentials_path("none"),
+            "generic".to_string(),
+        );
+
+        assert_eq!(
+            r.unwrap().sts_endpoint_url,
+            Some(String::from("http://localhost:5000"))
+        );
+    }
+
+    #[test]
+    fn it_loads_the_default_section() {
+        let r = Profile::load_from(
+            fixtures_path("static"),
+            credentials_path("static"),
+            "default".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(r.role_arn, None);
+        assert_eq!(r.region, Some(String::from("eu-west-1")));
+        assert_eq!(
+            r.static_credentials,
+            Some(StaticCredentials {
+                access_key_id: String::from("AKIADEFAULT"),
+                secret_access_key: String::from("default-secret"),
+                session_token: None,
+            })
+        );
+    }
+
+    #[test]
+    fn it_reads_static_keys_from_the_credentials_file() {
+        let r = Profile::load_from(
+            fixtures_path("static"),
+            credentials_path("static"),
+            "keys_only".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(r.mfa_serial, Some(String::from("example-mfa")));
+        assert_eq!(
+            r.static_credentials,
+            Some(StaticCredentials {
+                access_key_id: String::from("AKIAKEYSONLY"),
+                secret_access_key: String::from("keys-only-secret"),
+                session_token: Some(String::from("keys-only-token")),
+            })
+        );
+    }
+
+    #[test]
+    fn it_loads_profiles_only_present_in_the_credentials_file() {
+        let r = Profile::load_from(
+            fixtures_path("unexistent"),
+            credentials_path("static"),
+            "credentials_only".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(r.region, None);
+        assert_eq!(
+            r.static_credentials.map(|c| c.access_key_id),
+            Some(String::from("AKIACREDENTIALSONLY"))
+        );
+    }
+
+    #[test]
+    fn it_reads_static_keys_from_the_config_file() {
+        let r = Profile::load_from(
+            fixtures_path("static"),
+            credentials_path("none"),
+            "inline".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            r.static_credentials.map(|c| c.access_key_id),
+            Some(String::from("AKIAINLINE"))
+        );
+    }
+
+    #[test]
+    fn it_chains_roles_from_the_default_profile() {
+        let r = Profile::load_chain(
+            fixtures_path("static"),
+            credentials_path("static"),
+            "admin".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].role_arn, None);
+        assert!(r[0].static_credentials.is_some());
+        assert_eq!(r[1].role_arn, Some(String::from("example-admin-arn")));
+    }
+
+    #[test]
+    fn it_reads_the_web_identity_token_file() {
+        let r = Profile::load_from(
+            fixtures_path("web_identity"),
+            credentials_path("none"),
+            "ci".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(r.role_arn, Some(String::from("example-ci-arn")));
+        assert_eq!(
+            r.web_identity_token_file,
+            Some(String::from("/var/run/secrets/token"))
+        );
+    }
+
+    #[test]
+    fn it_starts_the_chain_from_the_web_identity_of_the_environment() {
+        let load = |user: &str| {
+            Profile::load_chain(
+                fixtures_path("web_identity"),
+                credentials_path("none"),
+                user.to_string(),
+            )
+        };
+        let web_identity = |role_arn: &str| {
+            Some(Profile::web_identity(
+                role_arn.to_string(),
+                "/token".to_string(),
+                None,
+            ))
+        };
+
+        let chain = Profile::with_web_identity(load("deploy"), web_identity("ci-arn")).unwrap();
+        assert_eq!(
+            chain.iter().map(|p| p.role_arn.clone()).collect::<Vec<_>>(),
+            vec![
+                Some(String::from("ci-arn")),
+                Some(String::from("example-deploy-arn"))
+            ]
+        );
+
+        let chain =
+            Profile::with_web_identity(load("deploy"), web_identity("example-deploy-arn")).unwrap();
+        assert_eq!(chain.len(), 1);
+        assert_eq!(
+            chain[0].web_identity_token_file,
+            Some(String::from("/token"))
+        );
+
+        let chain = Profile::with_web_identity(load("ci"), web_identity("other-arn")).unwrap();
+        assert_eq!(
+            chain[0].web_identity_token_file,
+            Some(String::from("/var/run/secrets/token"))
+        );
+
+        assert_eq!(
+            Profile::with_web_identity(
+                Profile::load_chain(
+                    fixtures_path("none"),
+                    credentials_path("none"),
+                    "deploy".to_string(),
+                ),
+                web_identity("ci-arn")
+            ),
+            Ok(vec![Profile::web_identity(
+                "ci-arn".to_string(),
+                "/token".to_string(),
+                None
+            )])
+        );
+        assert_eq!(
+            Profile::with_web_identity(load("unknown"), web_identity("ci-arn")),
+            Err(Error::ProfileNotFound)
+        );
+        assert_eq!(
+            Profile::with_web_identity(load("unknown"), None),
+            Err(Error::ProfileNotFound)
+        );
+    }
+
+    #[test]
+    fn it_reads_the_saml_assertion_source() {
+        let load = |user: &str| {
+            Profile::load_from(
+                fixtures_path("saml"),
+                credentials_path("none"),
+                user.to_string(),
+            )
+            .unwrap()
+        };
+
+        let okta = load("okta");
+        assert_eq!(okta.role_arn, None);
+        assert_eq!(
+            okta.saml_assertion,
+            Some(SamlAssertion::Command(String::from("okta-saml --app aws")))
+        );
+        assert_eq!(
+            okta.saml_principal_arn,
+            Some(String::from("arn:aws:iam::123456789012:saml-provider/idp"))
+        );
+        assert_eq!(
+            load("okta-admin").saml_assertion,
+            Some(SamlAssertion::Stdin)
+        );
+    }
+
+    #[test]
+    fn it_chains_roles_from_the_credential_process() {
+        let chain = Profile::load_chain(
+            fixtures_path("credential_process"),
+            credentials_path("none"),
+            "deploy".to_string(),
+        );
+        let web_identity = Profile::web_identity("ci-arn".to_string(), "/token".to_string(), None);
+
+        let chain = Profile::with_web_identity(chain, Some(web_identity)).unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[0].role_arn, None);
+        assert_eq!(
+            chain[0].credential_process,
+            Some(String::from("vault-aws-helper --role base"))
+        );
+        assert_eq!(chain[1].role_arn, Some(String::from("example-deploy-arn")));
+    }
+
+    #[test]
+    fn it_keeps_the_saml_chains_from_the_web_identity() {
+        let web_identity = Profile::web_identity("ci-arn".to_string(), "/token".to_string(), None);
+
+        for user in &["okta", "okta-admin"] {
+            let chain = Profile::load_chain(
+                fixtures_path("saml"),
+                credentials_path("none"),
+                user.to_string(),
+            );
+            let chain = Profile::with_web_identity(chain, Some(web_identity.clone())).unwrap();
+
+            assert_eq!(chain.len(), 1);
+            assert!(chain[0].saml_assertion.is_some());
+            assert_eq!(chain[0].web_identity_token_file, None);
+        }
+    }
+
+    #[test]
+    fn it_keeps_the_sso_chains_from_the_web_identity() {
+        let web_identity = Profile::web_identity("ci-arn".to_string(), "/token".to_string(), None);
+
+        for user in &["legacy", "admin"] {
+            let chain = Profile::load_chain(
+                fixtures_path("sso"),
+                credentials_path("none"),
+                user.to_string(),
+            );
+            let chain = Profile::with_web_identity(chain, Some(web_identity.clone())).unwrap();
+
+            assert!(chain[0].sso_session.is_some());
+            assert_eq!(chain[0].web_identity_token_file, None);
+        }
+    }
+
+    #[test]
+    fn it_reads_the_sso_settings() {
+        let load = |user: &str| {
+            Profile::load_from(
+                fixtures_path("sso"),
+                credentials_path("none"),
+                user.to_string(),
+            )
+        };
+        let session = SsoSession {
+            name: Some(String::from("my-sso")),
+            start_url: String::from("https://example.awsapps.com/start"),
+            region: String::from("eu-west-1"),
+            registration_scopes: vec![
+                String::from("sso:account:access"),
+                String::from("sso:other"),
+            ],
+        };
+
+        let dev = load("dev").unwrap();
+        assert_eq!(dev.role_arn, None);
+        assert_eq!(dev.sso_session, Some(session.clone()));
+        assert_eq!(dev.sso_account_id, Some(String::from("111122223333")));
+        assert_eq!(dev.sso_role_name, Some(String::from("Developer")));
+
+        assert_eq!(
+            load("legacy").unwrap().sso_session,
+            Some(SsoSession {
+                name: None,
+                start_url: String::from("https://legacy.awsapps.com/start"),
+                region: String::from("us-east-1"),
+                registration_scopes: vec![],
+            })
+        );
+        assert_eq!(load("orphan"), Err(Error::ProfileSsoSessionNotFound));
+        assert_eq!(
+            Profile::load_sso_session(fixtures_path("sso"), "my-sso"),
+            Ok(session)
+        );
+    }
 }
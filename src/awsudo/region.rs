@@ -0,0 +1,333 @@
+extern crate rusoto_core;
+
+use self::rusoto_core::Region;
+
+use awsudo::error::Error;
+use awsudo::profile::Profile;
+use std::env;
+use std::path::PathBuf;
+
+const AWS_GLOBAL_STS_ENDPOINT: &str = "https://sts.amazonaws.com";
+const AWS_GLOBAL_STS_REGION: &str = "us-east-1";
+
+// Regions served by the global STS endpoint when `sts_regional_endpoints=legacy`
+const AWS_LEGACY_STS_REGIONS: [&str; 15] = [
+    "ap-northeast-1",
+    "ap-south-1",
+    "ap-southeast-1",
+    "ap-southeast-2",
+    "ca-central-1",
+    "eu-central-1",
+    "eu-north-1",
+    "eu-west-1",
+    "eu-west-2",
+    "eu-west-3",
+    "sa-east-1",
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+];
+
+/// Builds the STS region for the given profile chain, reading the environment and the
+/// `[default]` section of the config file.
+pub fn from_config(
+    cli: Option<String>,
+    cli_endpoint: Option<String>,
+    profiles: &[Profile],
+    config: PathBuf,
+) -> Result<Region, Error> {
+    let region = resolve(
+        cli,
+        env::var("AWS_REGION")
+            .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+            .ok(),
+        profiles,
+        Profile::default_setting(config.clone(), "region"),
+    );
+
+    if let Some(url) = endpoint(
+        cli_endpoint,
+        env::var("AWS_ENDPOINT_URL_STS").ok(),
+        profiles,
+    ) {
+        return Ok(Region::Custom {
+            name: region.unwrap_or_else(|| AWS_GLOBAL_STS_REGION.to_owned()),
+            endpoint: url,
+        });
+    }
+
+    let endpoints = regional_endpoints(
+        env::var("AWS_STS_REGIONAL_ENDPOINTS").ok(),
+        profiles,
+        Profile::default_setting(config, "sts_regional_endpoints"),
+    );
+
+    match endpoints.as_deref() {
+        None | Some("regional") => Ok(sts(region, true)),
+        Some("legacy") => Ok(sts(region, false)),
+        Some(_) => Err(Error::ProfileInvalidStsRegionalEndpoints),
+    }
+}
+
+/// Picks the first region set, in order: command line, environment, the requested profile,
+/// its source profiles and the `[default]` section.
+pub fn resolve(
+    cli: Option<String>,
+    environment: Option<String>,
+    profiles: &[Profile],
+    default: Option<String>,
+) -> Option<String> {
+    cli.or(environment)
+        .or_else(|| profiles.iter().rev().find_map(|p| p.region.clone()))
+        .or(default)
+}
+
+/// Picks the first `sts_regional_endpoints` set, in the same order as the region: environment,
+/// the requested profile, its source profiles and the `[default]` section.
+pub fn regional_endpoints(
+    environment: Option<String>,
+    profiles: &[Profile],
+    default: Option<String>,
+) -> Option<String> {
+    environment
+        .or_else(|| {
+            profiles
+                .iter()
+                .rev()
+                .find_map(|p| p.sts_regional_endpoints.clone())
+        })
+        .or(default)
+}
+
+/// Picks the first custom STS endpoint set, in order: command line, environment, the requested
+/// profile and its source profiles.
+pub fn endpoint(
+    cli: Option<String>,
+    environment: Option<String>,
+    profiles: &[Profile],
+) -> Option<String> {
+    cli.or(environment).or_else(|| {
+        profiles
+            .iter()
+            .rev()
+            .find_map(|p| p.sts_endpoint_url.clone())
+    })
+}
+
+/// Maps a region to its STS endpoint, falling back to the global endpoint when there is no
+/// region or when a legacy region is not meant to use its regional endpoint.
+pub fn sts(region: Option<String>, regional_endpoints: bool) -> Region {
+    match region {
+        None => global(),
+        Some(ref name)
+            if !regional_endpoints && AWS_LEGACY_STS_REGIONS.contains(&name.as_str()) =>
+        {
+            global()
+        }
+        Some(name) => match name.parse::<Region>() {
+            Ok(region) => region,
+            Err(_) => Region::Custom {
+                endpoint: format!("https://sts.{}.amazonaws.com", name),
+                name,
+            },
+        },
+    }
+}
+
+fn global() -> Region {
+    Region::Custom {
+        name: AWS_GLOBAL_STS_REGION.to_owned(),
+        endpoint: AWS_GLOBAL_STS_ENDPOINT.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::profile::Profile;
+    use awsudo::region;
+    use awsudo::region::rusoto_core::Region;
+    use std::path::PathBuf;
+
+    fn fixtures_path(file: &str) -> PathBuf {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures/config/");
+        p.push(file);
+        p
+    }
+
+    fn credentials_path(file: &str) -> PathBuf {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures/credentials/");
+        p.push(file);
+        p
+    }
+
+    fn chain() -> Vec<Profile> {
+        Profile::load_chain(
+            fixtures_path("chained"),
+            credentials_path("none"),
+            "prod".to_string(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_prefers_the_cli_region() {
+        assert_eq!(
+            region::resolve(
+                Some("sa-east-1".to_string()),
+                Some("us-west-2".to_string()),
+                &chain(),
+                Some("ap-southeast-2".to_string())
+            ),
+            Some("sa-east-1".to_string())
+        );
+    }
+
+    #[test]
+    fn it_prefers_the_environment_over_profiles() {
+        assert_eq!(
+            region::resolve(None, Some("us-west-2".to_string()), &chain(), None),
+            Some("us-west-2".to_string())
+        );
+    }
+
+    #[test]
+    fn it_prefers_the_requested_profile_over_its_sources() {
+        assert_eq!(
+            region::resolve(None, None, &chain(), None),
+            Some("us-east-1".to_string())
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_the_source_profile_region() {
+        let profiles = Profile::load_chain(
+            fixtures_path("chained"),
+            credentials_path("none"),
+            "hub".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            region::resolve(None, None, &profiles, Some("ap-southeast-2".to_string())),
+            Some("eu-west-1".to_string())
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_the_default_region() {
+        let profiles = Profile::load_chain(
+            fixtures_path("missing_values"),
+            credentials_path("none"),
+            "missing_region".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            region::resolve(None, None, &profiles, Some("ap-southeast-2".to_string())),
+            Some("ap-southeast-2".to_string())
+        );
+    }
+
+    #[test]
+    fn it_prefers_the_cli_endpoint() {
+        let profiles = Profile::load_chain(
+            fixtures_path("endpoints"),
+            credentials_path("none"),
+            "sts_specific".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            region::endpoint(
+                Some("http://localhost:1".to_string()),
+                Some("http://localhost:2".to_string()),
+                &profiles
+            ),
+            Some("http://localhost:1".to_string())
+        );
+        assert_eq!(
+            region::endpoint(None, Some("http://localhost:2".to_string()), &profiles),
+            Some("http://localhost:2".to_string())
+        );
+    }
+
+    #[test]
+    fn it_falls_back_to_the_profile_endpoint() {
+        let profiles = Profile::load_chain(
+            fixtures_path("endpoints"),
+            credentials_path("none"),
+            "sts_specific".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            region::endpoint(None, None, &profiles),
+            Some("http://localhost:4566".to_string())
+        );
+        assert_eq!(region::endpoint(None, None, &chain()), None);
+    }
+
+    #[test]
+    fn it_resolves_the_regional_endpoints_across_the_chain() {
+        let profiles = vec![
+            Profile {
+                sts_regional_endpoints: Some("legacy".to_string()),
+                ..Default::default()
+            },
+            Profile::default(),
+        ];
+
+        assert_eq!(
+            region::regional_endpoints(None, &profiles, Some("regional".to_string())),
+            Some("legacy".to_string())
+        );
+        assert_eq!(
+            region::regional_endpoints(Some("regional".to_string()), &profiles, None),
+            Some("regional".to_string())
+        );
+        assert_eq!(
+            region::regional_endpoints(None, &[Profile::default()], Some("legacy".to_string())),
+            Some("legacy".to_string())
+        );
+    }
+
+    #[test]
+    fn it_uses_the_regional_endpoint() {
+        assert_eq!(
+            region::sts(Some("us-west-2".to_string()), true),
+            Region::UsWest2
+        );
+    }
+
+    #[test]
+    fn it_uses_a_custom_endpoint_for_unknown_regions() {
+        assert_eq!(
+            region::sts(Some("ap-east-1".to_string()), true),
+            Region::Custom {
+                name: "ap-east-1".to_string(),
+                endpoint: "https://sts.ap-east-1.amazonaws.com".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn it_uses_the_global_endpoint_for_legacy_regions() {
+        let global = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "https://sts.amazonaws.com".to_string(),
+        };
+
+        assert_eq!(region::sts(Some("eu-central-1".to_string()), false), global);
+        assert_eq!(region::sts(None, true), global);
+        assert_eq!(
+            region::sts(Some("ap-east-1".to_string()), false),
+            Region::Custom {
+                name: "ap-east-1".to_string(),
+                endpoint: "https://sts.ap-east-1.amazonaws.com".to_string(),
+            }
+        );
+    }
+}
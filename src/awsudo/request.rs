@@ -1,66 +1,695 @@
+extern crate chrono;
+extern crate futures;
 extern crate rusoto_core;
 extern crate rusoto_sts;
 
-use self::rusoto_core::Region;
-use self::rusoto_sts::{AssumeRoleRequest, Sts, StsClient};
+use self::chrono::{DateTime, Utc};
+use self::futures::Future;
+use self::rusoto_core::credential::StaticProvider;
+use self::rusoto_core::request::{BufferedHttpResponse, HttpDispatchError};
+use self::rusoto_core::signature::SignedRequest;
+use self::rusoto_core::{DispatchSignedRequest, HttpClient, Region, RusotoError, RusotoFuture};
+use self::rusoto_sts::{
+    AssumeRoleRequest, AssumeRoleWithSAMLError, AssumeRoleWithWebIdentityError,
+    GetSessionTokenRequest, Sts, StsClient,
+};
 
+use awsudo::cache;
+use awsudo::cache::Cache;
+use awsudo::credential_process;
 use awsudo::credentials::Credentials;
+use awsudo::error;
+use awsudo::error::Error;
 use awsudo::fetcher::Fetcher;
-use awsudo::profile::Profile;
+use awsudo::profile::{Profile, SamlAssertion, StaticCredentials};
+use awsudo::saml;
+use awsudo::sso;
+use awsudo::sso::Endpoints;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::time::Duration;
 
 const AWS_DEFAULT_SESSION_NAME: &str = "awsudo";
+const MFA_MAX_ATTEMPTS: usize = 3;
+const STS_API_VERSION: &str = "2011-06-15";
+
+/// Builds the cache of the session of an MFA device from its serial.
+pub type MfaSessions = Box<dyn Fn(&str) -> Cache>;
 
 pub struct Request {
-    pub profile: Profile,
-    pub token_collector: fn(String) -> Option<String>,
+    pub profiles: Vec<Profile>,
+    pub region: Region,
+    pub token_collector: fn(String) -> Result<String, Error>,
+    pub mfa_sessions: Option<MfaSessions>,
+    pub mfa_session_duration: Option<i64>,
+    pub sso_endpoints: Endpoints,
 }
 
 impl Request {
-    pub fn new(profile: Profile, token_collector: fn(String) -> Option<String>) -> Request {
+    /// Builds a request for a chain of profiles, as returned by `Profile::load_chain`,
+    /// where each role is assumed with the credentials of the previous one.
+    pub fn new(
+        profiles: Vec<Profile>,
+        region: Region,
+        token_collector: fn(String) -> Result<String, Error>,
+    ) -> Request {
         Request {
-            profile,
+            profiles,
+            region,
             token_collector,
+            mfa_sessions: None,
+            mfa_session_duration: None,
+            sso_endpoints: Endpoints::default(),
         }
     }
-}
 
-impl Fetcher for Request {
-    fn fetch(&self) -> Result<Credentials, &'static str> {
-        let base_request = AssumeRoleRequest {
-            role_arn: self.profile.role_arn.clone(),
-            role_session_name: AWS_DEFAULT_SESSION_NAME.to_owned(),
+    /// Reuses a session of the MFA device, from `GetSessionToken`, to assume every role
+    /// protected by it, so the token is only asked for once per session. Each device gets
+    /// its own cache, built by `sessions` from its serial.
+    pub fn with_mfa_sessions(self, sessions: MfaSessions, duration: Option<i64>) -> Request {
+        Request {
+            mfa_sessions: Some(sessions),
+            mfa_session_duration: duration,
+            ..self
+        }
+    }
+
+    /// Exchanges the SSO tokens at the given endpoints instead of the ones of their region.
+    pub fn with_sso_endpoints(self, endpoints: Endpoints) -> Request {
+        Request {
+            sso_endpoints: endpoints,
+            ..self
+        }
+    }
+
+    fn client(&self, source: Option<StaticProvider>) -> Result<StsClient, Error> {
+        match source {
+            None => Ok(StsClient::new(self.region.clone())),
+            Some(provider) => Ok(StsClient::new_with(http()?, provider, self.region.clone())),
+        }
+    }
+
+    // Calls that take no AWS credentials, which the STS client would still sign, failing with
+    // the errors of the action `E`
+    fn unsigned<E: std::error::Error + std::fmt::Debug + 'static>(
+        &self,
+        params: &[(&str, String)],
+    ) -> Result<String, Error> {
+        let mut request = SignedRequest::new("POST", "sts", &self.region, "/");
+        request.set_content_type("application/x-www-form-urlencoded".to_owned());
+        request.set_payload(Some(form_encode(params).into_bytes()));
+
+        match send(request, |code, message| Error::Sts { code, message })? {
+            ref response if response.status.is_success() => Ok(response.body_as_str().to_owned()),
+            response => Err(Error::from_rusoto(RusotoError::<E>::Unknown(response))),
+        }
+    }
+
+    fn assume(
+        &self,
+        profile: &Profile,
+        role_arn: &str,
+        mfa_serial: Option<&String>,
+        source: Option<StaticProvider>,
+    ) -> Result<Credentials, Error> {
+        let request = AssumeRoleRequest {
+            role_arn: role_arn.to_owned(),
+            role_session_name: session_name(profile),
+            duration_seconds: profile.duration_seconds,
+            external_id: profile.external_id.clone(),
             ..Default::default()
         };
 
-        let request = match self.profile.mfa_serial.clone() {
-            Some(serial) => match (self.token_collector)(serial.clone()) {
-                Some(token) => AssumeRoleRequest {
-                    serial_number: Some(serial.to_string()),
-                    token_code: Some(token.to_string()),
-                    ..base_request
+        self.with_mfa(mfa_serial, |mfa| {
+            let request = match mfa {
+                Some((serial, token)) => AssumeRoleRequest {
+                    serial_number: Some(serial),
+                    token_code: Some(token),
+                    ..request.clone()
                 },
-                None => base_request,
-            },
-            None => base_request,
+                None => request.clone(),
+            };
+
+            match self.client(source.clone())?.assume_role(request).sync() {
+                Ok(response) => convert(response.credentials),
+                Err(e) => Err(Error::from_rusoto(e)),
+            }
+        })
+    }
+
+    // The token, read again for every request as it is rotated, is the only credential needed
+    fn assume_with_web_identity(
+        &self,
+        profile: &Profile,
+        role_arn: &str,
+        token_file: &str,
+    ) -> Result<Credentials, Error> {
+        let token =
+            fs::read_to_string(token_file).map_err(|_| Error::ProfileWebIdentityTokenNotFound)?;
+
+        let mut params = vec![
+            ("Action", "AssumeRoleWithWebIdentity".to_owned()),
+            ("Version", STS_API_VERSION.to_owned()),
+            ("RoleArn", role_arn.to_owned()),
+            ("RoleSessionName", session_name(profile)),
+            ("WebIdentityToken", token.trim().to_owned()),
+        ];
+        if let Some(duration) = profile.duration_seconds {
+            params.push(("DurationSeconds", duration.to_string()));
+        }
+
+        from_xml(&self.unsigned::<AssumeRoleWithWebIdentityError>(&params)?)
+    }
+
+    // The role is the one of the profile, or picked among the ones granted by the assertion
+    fn assume_with_saml(
+        &self,
+        profile: &Profile,
+        assertion: &SamlAssertion,
+    ) -> Result<Credentials, Error> {
+        let encoded = saml::read(assertion)?;
+        let role = saml::choose(
+            saml::roles(&encoded)?,
+            profile.role_arn.as_ref(),
+            profile.saml_principal_arn.as_ref(),
+            saml::collect,
+        )?;
+
+        let mut params = vec![
+            ("Action", "AssumeRoleWithSAML".to_owned()),
+            ("Version", STS_API_VERSION.to_owned()),
+            ("RoleArn", role.role_arn),
+            ("PrincipalArn", role.principal_arn),
+            ("SAMLAssertion", encoded),
+        ];
+        if let Some(duration) = profile.duration_seconds {
+            params.push(("DurationSeconds", duration.to_string()));
+        }
+
+        from_xml(&self.unsigned::<AssumeRoleWithSAMLError>(&params)?)
+    }
+
+    // Profiles with static keys and no role, and MFA devices, get temporary credentials for
+    // the keys
+    fn session(
+        &self,
+        mfa_serial: Option<&String>,
+        duration_seconds: Option<i64>,
+        source: Option<StaticProvider>,
+    ) -> Result<Credentials, Error> {
+        self.with_mfa(mfa_serial, |mfa| {
+            let (serial_number, token_code) = match mfa {
+                Some((serial, token)) => (Some(serial), Some(token)),
+                None => (None, None),
+            };
+            let request = GetSessionTokenRequest {
+                duration_seconds,
+                serial_number,
+                token_code,
+            };
+
+            match self
+                .client(source.clone())?
+                .get_session_token(request)
+                .sync()
+            {
+                Ok(response) => convert(response.credentials),
+                Err(e) => Err(Error::from_rusoto(e)),
+            }
+        })
+    }
+
+    // The session of the MFA device, from its cache or requested with a new token, waiting for
+    // any other process already requesting it
+    fn mfa_session(
+        &self,
+        sessions: &dyn Fn(&str) -> Cache,
+        keys: Option<&StaticCredentials>,
+        serial: &str,
+        source: Option<StaticProvider>,
+    ) -> Result<Credentials, Error> {
+        let cache =
+            sessions(serial).with_fingerprint(cache::mfa_fingerprint(serial, keys, &self.region));
+        if let Ok(credentials) = cache.fetch() {
+            return Ok(credentials);
+        }
+
+        let _lock = cache
+            .lock(Duration::from_secs(cache::CACHE_LOCK_TIMEOUT_SECONDS))
+            .ok();
+        if let Ok(credentials) = cache.fetch() {
+            return Ok(credentials);
+        }
+
+        let credentials =
+            self.session(Some(&serial.to_owned()), self.mfa_session_duration, source)?;
+        // The token was already typed, the session is still good for this run
+        if let Err(e) = cache.persist(&credentials) {
+            eprintln!("awsudo: {}, going on without it", e);
+        }
+
+        Ok(credentials)
+    }
+
+    // Sends the request with the serial and a token of the MFA device, if any, asking for
+    // another token when it is rejected
+    fn with_mfa<F>(&self, mfa_serial: Option<&String>, send: F) -> Result<Credentials, Error>
+    where
+        F: Fn(Option<(String, String)>) -> Result<Credentials, Error>,
+    {
+        let serial = match mfa_serial {
+            Some(serial) => serial.clone(),
+            None => return send(None),
         };
 
-        match StsClient::new(Region::EuCentral1)
-            .assume_role(request)
-            .sync()
-        {
-            Err(_) => Err("Request to AWS failed"),
-            Ok(response) => match response.credentials {
-                Some(c) => Ok(Credentials {
-                    access_key_id: c.access_key_id,
-                    secret_access_key: c.secret_access_key,
-                    session_token: c.session_token,
-                    cached: false,
-                }),
-                None => Err("Request to AWS failed"),
-            },
+        let mut attempt = 1;
+        loop {
+            let result = (self.token_collector)(serial.clone())
+                .and_then(|token| send(Some((serial.clone(), token))));
+
+            match result {
+                Err(ref e @ Error::MfaRejected(_)) | Err(ref e @ Error::MfaInvalidFormat)
+                    if attempt < MFA_MAX_ATTEMPTS =>
+                {
+                    eprintln!("awsudo: {}, please try again", e);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Fetcher for Request {
+    fn fetch(&self) -> Result<Credentials, Error> {
+        let mut source: Option<StaticProvider> = None;
+        let mut credentials: Option<Credentials> = None;
+        // The keys behind the source, while it still comes from the ones of a profile
+        let mut source_keys: Option<StaticCredentials> = None;
+
+        for (i, profile) in self.profiles.iter().enumerate() {
+            // Each hop uses the credentials of the previous one, or its own static keys
+            let (hop_source, hop_keys) = match source.take() {
+                Some(provider) => (Some(provider), source_keys.take()),
+                None => (
+                    profile
+                        .static_credentials
+                        .as_ref()
+                        .map(|keys| keys.provider()),
+                    profile.static_credentials.clone(),
+                ),
+            };
+
+            if let Some(ref session) = profile.sso_session {
+                let assumed = match (
+                    profile.sso_account_id.as_ref(),
+                    profile.sso_role_name.as_ref(),
+                ) {
+                    (Some(account_id), Some(role_name)) => {
+                        sso::role_credentials(session, account_id, role_name, &self.sso_endpoints)?
+                    }
+                    _ => return Err(Error::ProfileSsoRoleNotFound),
+                };
+                source = Some(assumed.provider());
+                credentials = Some(assumed);
+                continue;
+            }
+
+            if let Some(ref assertion) = profile.saml_assertion {
+                let assumed = self.assume_with_saml(profile, assertion)?;
+                source = Some(assumed.provider());
+                credentials = Some(assumed);
+                continue;
+            }
+
+            if let (Some(role_arn), Some(token_file)) = (
+                profile.role_arn.as_ref(),
+                profile.web_identity_token_file.as_ref(),
+            ) {
+                let assumed = self.assume_with_web_identity(profile, role_arn, token_file)?;
+                source = Some(assumed.provider());
+                credentials = Some(assumed);
+                continue;
+            }
+
+            // Without keys of its own, the chain may start from the ones printed by the
+            // credential_process of the profile
+            let process = match profile.credential_process {
+                Some(ref command) if hop_source.is_none() => {
+                    Some(credential_process::run(command)?)
+                }
+                _ => None,
+            };
+            let keys = hop_keys.or_else(|| process.as_ref().map(|p| p.keys.clone()));
+            let hop_source = hop_source.or_else(|| process.as_ref().map(|p| p.keys.provider()));
+
+            // GetSessionToken only accepts long-term keys, the ones of a profile or the ambient
+            // credentials, not the ones of an assumed role
+            let long_term = match keys {
+                Some(ref keys) => keys.session_token.is_none(),
+                None => hop_source.is_none() && ambient_long_term(&|name| env::var_os(name)),
+            };
+
+            match (
+                profile.role_arn.as_ref(),
+                profile.mfa_serial.as_ref(),
+                self.mfa_sessions.as_ref(),
+            ) {
+                (Some(role_arn), Some(serial), Some(sessions)) if long_term => {
+                    let session = match self.mfa_session(
+                        &**sessions,
+                        keys.as_ref(),
+                        serial,
+                        hop_source.clone(),
+                    ) {
+                        Ok(session) => Some(session),
+                        // The ambient credentials can still be temporary ones, e.g.: of an
+                        // instance role, which only AssumeRole takes
+                        Err(Error::Sts { ref code, .. })
+                            if keys.is_none() && code == "AccessDenied" =>
+                        {
+                            None
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let assumed = match session {
+                        Some(session) => {
+                            self.assume(profile, role_arn, None, Some(session.provider()))?
+                        }
+                        None => self.assume(profile, role_arn, Some(serial), hop_source)?,
+                    };
+                    source = Some(assumed.provider());
+                    credentials = Some(assumed);
+                }
+                (Some(role_arn), _, _) => {
+                    let assumed =
+                        self.assume(profile, role_arn, profile.mfa_serial.as_ref(), hop_source)?;
+                    source = Some(assumed.provider());
+                    credentials = Some(assumed);
+                }
+                (None, _, _) if i == self.profiles.len() - 1 => {
+                    // Temporary credentials, of the helper or static keys with a session token,
+                    // are injected as they are, GetSessionToken only takes long-term keys
+                    let temporary = match (process.as_ref(), keys.as_ref()) {
+                        (Some(process), _) => process.credentials(),
+                        (None, Some(keys)) if profile.mfa_serial.is_none() => {
+                            Credentials::temporary(keys, None)
+                        }
+                        _ => None,
+                    };
+                    credentials = match temporary {
+                        Some(temporary) => Some(temporary),
+                        None => Some(self.session(
+                            profile.mfa_serial.as_ref(),
+                            profile.duration_seconds,
+                            hop_source,
+                        )?),
+                    };
+                }
+                (None, _, _) => {
+                    source = hop_source;
+                    source_keys = keys;
+                }
+            }
         }
+
+        match credentials {
+            Some(c) => Ok(c),
+            None => Err(Error::ProfileNotFound),
+        }
+    }
+}
+
+impl Credentials {
+    fn provider(&self) -> StaticProvider {
+        StaticProvider::new(
+            self.access_key_id.clone(),
+            self.secret_access_key.clone(),
+            Some(self.session_token.clone()),
+            None,
+        )
+    }
+}
+
+impl StaticCredentials {
+    fn provider(&self) -> StaticProvider {
+        StaticProvider::new(
+            self.access_key_id.clone(),
+            self.secret_access_key.clone(),
+            self.session_token.clone(),
+            None,
+        )
+    }
+}
+
+// The ambient credentials are long-term keys unless they come with a session token, or from
+// the container credentials endpoint
+fn ambient_long_term(environment: &dyn Fn(&str) -> Option<OsString>) -> bool {
+    [
+        "AWS_SESSION_TOKEN",
+        "AWS_SECURITY_TOKEN",
+        "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI",
+        "AWS_CONTAINER_CREDENTIALS_FULL_URI",
+    ]
+    .iter()
+    .all(|name| environment(name).is_none())
+}
+
+fn http() -> Result<HttpClient, Error> {
+    HttpClient::new().map_err(|e| Error::Sts {
+        code: "HttpClient".to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Sends the request as it was built, without signing it, returning the response whatever its
+/// status, for the calls that take no AWS credentials. Failing to reach the service is
+/// reported through `failure`, given a code and a message.
+pub fn send(
+    request: SignedRequest,
+    failure: fn(String, String) -> Error,
+) -> Result<BufferedHttpResponse, Error> {
+    let client = HttpClient::new().map_err(|e| failure("HttpClient".to_string(), e.to_string()))?;
+    let response = client
+        .dispatch(request, None)
+        .and_then(|response| response.buffer())
+        .map_err(RusotoError::from);
+
+    RusotoFuture::<BufferedHttpResponse, HttpDispatchError>::from_future(response)
+        .sync()
+        .map_err(|e| failure("HttpDispatch".to_string(), e.to_string()))
+}
+
+fn session_name(profile: &Profile) -> String {
+    profile
+        .role_session_name
+        .clone()
+        .unwrap_or_else(|| AWS_DEFAULT_SESSION_NAME.to_owned())
+}
+
+/// Encodes the parameters of a form or a query string.
+pub fn form_encode(params: &[(&str, String)]) -> String {
+    params
+        .iter()
+        .map(|&(key, ref value)| {
+            let value: String = value
+                .bytes()
+                .map(|b| match b {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        (b as char).to_string()
+                    }
+                    _ => format!("%{:02X}", b),
+                })
+                .collect();
+            format!("{}={}", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Reads the credentials of the response of the calls sent without the STS client
+fn from_xml(body: &str) -> Result<Credentials, Error> {
+    match (
+        error::xml_value(body, "AccessKeyId"),
+        error::xml_value(body, "SecretAccessKey"),
+        error::xml_value(body, "SessionToken"),
+        error::xml_value(body, "Expiration"),
+    ) {
+        (Some(access_key_id), Some(secret_access_key), Some(session_token), Some(expiration)) => {
+            convert(Some(rusoto_sts::Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token,
+                expiration,
+            }))
+        }
+        _ => Err(Error::StsMissingCredentials),
     }
 }
 
-//TODO: Find a way to properly test this
-// Right now it is a bit tricky considering the external types/requests/side-effects
+fn convert(credentials: Option<rusoto_sts::Credentials>) -> Result<Credentials, Error> {
+    match credentials {
+        Some(c) => match c.expiration.parse::<DateTime<Utc>>() {
+            Ok(expires_at) => Ok(Credentials {
+                access_key_id: c.access_key_id,
+                secret_access_key: c.secret_access_key,
+                session_token: c.session_token,
+                expires_at,
+                cached: false,
+                cacheable: true,
+            }),
+            Err(_) => Err(Error::StsInvalidExpiration),
+        },
+        None => Err(Error::StsMissingCredentials),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate chrono;
+    extern crate rusoto_core;
+
+    use self::rusoto_core::Region;
+    use awsudo::cache;
+    use awsudo::cache::Cache;
+    use awsudo::credentials::Credentials;
+    use awsudo::error::Error;
+    use awsudo::fetcher::Fetcher;
+    use awsudo::profile::Profile;
+    use awsudo::request;
+    use awsudo::request::Request;
+    use std::ffi::OsString;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const MFA_SERIAL: &str = "arn:aws:iam::000000000000:mfa/user";
+
+    static TOKENS: AtomicUsize = AtomicUsize::new(0);
+
+    fn device(_serial: String) -> Result<String, Error> {
+        TOKENS.fetch_add(1, Ordering::SeqCst);
+        Ok("123456".to_string())
+    }
+
+    fn fixtures_path(path: &str) -> PathBuf {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures");
+        p.push(path);
+        p
+    }
+
+    fn chain(user: &str) -> Vec<Profile> {
+        Profile::load_chain(
+            fixtures_path("config/mfa_session"),
+            fixtures_path("credentials/static"),
+            user.to_string(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn it_form_encodes_the_parameters() {
+        assert_eq!(
+            request::form_encode(&[
+                ("RoleArn", "arn:aws:iam::1:role/ci".to_string()),
+                ("WebIdentityToken", "a.b-c_d~e+f=".to_string()),
+            ]),
+            "RoleArn=arn%3Aaws%3Aiam%3A%3A1%3Arole%2Fci&WebIdentityToken=a.b-c_d~e%2Bf%3D"
+        );
+    }
+
+    #[test]
+    fn it_reads_the_credentials_of_the_response() {
+        let credentials = request::from_xml(
+            "<AssumeRoleWithWebIdentityResponse><AssumeRoleWithWebIdentityResult><Credentials>\
+             <AccessKeyId>ASIA</AccessKeyId><SecretAccessKey>secret</SecretAccessKey>\
+             <SessionToken>token</SessionToken><Expiration>2031-02-03T04:05:06Z</Expiration>\
+             </Credentials></AssumeRoleWithWebIdentityResult></AssumeRoleWithWebIdentityResponse>",
+        )
+        .unwrap();
+
+        assert_eq!(credentials.access_key_id, "ASIA");
+        assert_eq!(credentials.session_token, "token");
+        assert!(!credentials.cached);
+        assert_eq!(
+            request::from_xml("<Credentials></Credentials>").err(),
+            Some(Error::StsMissingCredentials)
+        );
+    }
+
+    #[test]
+    fn it_tells_temporary_ambient_credentials() {
+        assert!(request::ambient_long_term(&|_| None));
+        assert!(!request::ambient_long_term(&|name| match name {
+            "AWS_SESSION_TOKEN" => Some(OsString::from("token")),
+            _ => None,
+        }));
+        assert!(!request::ambient_long_term(&|name| match name {
+            "AWS_CONTAINER_CREDENTIALS_FULL_URI" => Some(OsString::from("http://127.0.0.1:1")),
+            _ => None,
+        }));
+    }
+
+    #[test]
+    fn it_injects_static_keys_with_a_session_token_as_they_are() {
+        // Nothing listens there, GetSessionToken would fail
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "http://127.0.0.1:1".to_string(),
+        };
+        let credentials = Request::new(chain("temporary"), region, device)
+            .fetch()
+            .unwrap();
+
+        assert_eq!(credentials.access_key_id, "ASIATEMPORARY");
+        assert_eq!(credentials.session_token, "temporary-token");
+        assert!(!credentials.cacheable);
+    }
+
+    #[test]
+    fn it_asks_for_the_token_once_for_the_roles_sharing_the_mfa_device() {
+        // Nothing listens there, every call to STS fails without leaving the machine
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "http://127.0.0.1:1".to_string(),
+        };
+        let dir = fixtures_path("tmp/request");
+        let request = |user: &str| {
+            let sessions_dir = dir.clone();
+            Request::new(chain(user), region.clone(), device).with_mfa_sessions(
+                Box::new(move |serial| {
+                    Cache::new(sessions_dir.clone(), &cache::mfa_session_name(serial))
+                }),
+                None,
+            )
+        };
+
+        assert!(request("prod").fetch().is_err());
+        assert_eq!(TOKENS.load(Ordering::SeqCst), 1);
+
+        // The session GetSessionToken returns for the keys of the source profile
+        Cache::new(dir.clone(), &cache::mfa_session_name(MFA_SERIAL))
+            .with_fingerprint(cache::mfa_fingerprint(
+                MFA_SERIAL,
+                chain("base")[0].static_credentials.as_ref(),
+                &region,
+            ))
+            .persist(&Credentials {
+                access_key_id: "ASIASESSION".to_string(),
+                secret_access_key: "session-secret".to_string(),
+                session_token: "session-token".to_string(),
+                expires_at: chrono::Utc::now() + chrono::Duration::hours(12),
+                cached: false,
+                cacheable: true,
+            })
+            .unwrap();
+
+        assert!(request("staging").fetch().is_err());
+        assert!(request("prod").fetch().is_err());
+        assert_eq!(TOKENS.load(Ordering::SeqCst), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+extern crate base64;
+extern crate xml;
+
+use self::xml::reader::{EventReader, XmlEvent};
+
+use awsudo::error::Error;
+use awsudo::profile::{Profile, SamlAssertion};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+
+const AWS_SAML_ROLE_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/Role";
+const TERMINAL: &str = "/dev/tty";
+
+/// A role granted by the assertion, with the identity provider it trusts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Role {
+    pub role_arn: String,
+    pub principal_arn: String,
+}
+
+/// Reads the base64 assertion from its file, stdin or the output of its helper command, which
+/// is run through `sh -c` and may interact with the user on the terminal.
+pub fn read(assertion: &SamlAssertion) -> Result<String, Error> {
+    let encoded = match *assertion {
+        SamlAssertion::File(ref path) => fs::read_to_string(path).ok(),
+        SamlAssertion::Stdin => {
+            let mut encoded = String::new();
+            io::stdin()
+                .read_to_string(&mut encoded)
+                .ok()
+                .map(|_| encoded)
+        }
+        SamlAssertion::Command(ref command) => Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok()),
+    };
+
+    // Assertions are often wrapped over several lines
+    match encoded.map(|e| e.split_whitespace().collect::<String>()) {
+        Some(ref e) if !e.is_empty() => Ok(e.clone()),
+        _ => Err(Error::SamlAssertionNotRead),
+    }
+}
+
+/// Refuses to read an assertion from stdin when stdin is `taken`, either by the command, e.g.:
+/// its input piped through awsudo, or by the refreshes of the credentials server, which would
+/// find it already read.
+pub fn check_stdin(profiles: &[Profile], taken: bool) -> Result<(), Error> {
+    let reads_stdin = profiles
+        .iter()
+        .any(|p| p.saml_assertion == Some(SamlAssertion::Stdin));
+
+    if taken && reads_stdin {
+        Err(Error::SamlAssertionStdinTaken)
+    } else {
+        Ok(())
+    }
+}
+
+/// Lists the roles of the `https://aws.amazon.com/SAML/Attributes/Role` attribute of the
+/// assertion, each value being a role and provider ARN pair, in any order.
+pub fn roles(encoded: &str) -> Result<Vec<Role>, Error> {
+    let decoded = base64::decode(encoded).map_err(|_| Error::SamlAssertionInvalid)?;
+
+    let mut roles = vec![];
+    let mut in_role_attribute = false;
+    let mut value: Option<String> = None;
+    for event in EventReader::new(decoded.as_slice()) {
+        match event.map_err(|_| Error::SamlAssertionInvalid)? {
+            XmlEvent::StartElement {
+                ref name,
+                ref attributes,
+                ..
+            } if name.local_name == "Attribute" => {
+                in_role_attribute = attributes
+                    .iter()
+                    .any(|a| a.name.local_name == "Name" && a.value == AWS_SAML_ROLE_ATTRIBUTE);
+            }
+            XmlEvent::StartElement { ref name, .. }
+                if in_role_attribute && name.local_name == "AttributeValue" =>
+            {
+                value = Some(String::new());
+            }
+            XmlEvent::Characters(ref text) => {
+                if let Some(ref mut value) = value {
+                    value.push_str(text);
+                }
+            }
+            XmlEvent::EndElement { ref name } if name.local_name == "AttributeValue" => {
+                if let Some(role) = value.take().and_then(|v| role(&v)) {
+                    roles.push(role);
+                }
+            }
+            XmlEvent::EndElement { ref name } if name.local_name == "Attribute" => {
+                in_role_attribute = false;
+            }
+            _ => {}
+        }
+    }
+
+    if roles.is_empty() {
+        Err(Error::SamlAssertionInvalid)
+    } else {
+        Ok(roles)
+    }
+}
+
+fn role(value: &str) -> Option<Role> {
+    let arns = value.split(',').map(|arn| arn.trim()).collect::<Vec<_>>();
+    let role_arn = arns.iter().find(|arn| arn.contains(":role/"))?;
+    let principal_arn = arns.iter().find(|arn| arn.contains(":saml-provider/"))?;
+
+    Some(Role {
+        role_arn: role_arn.to_string(),
+        principal_arn: principal_arn.to_string(),
+    })
+}
+
+/// Picks the role given by the profile among the ones of the assertion, letting `chooser`
+/// pick one when the profile gives none and the assertion has more than one.
+pub fn choose(
+    roles: Vec<Role>,
+    role_arn: Option<&String>,
+    principal_arn: Option<&String>,
+    chooser: fn(&[Role]) -> Result<usize, Error>,
+) -> Result<Role, Error> {
+    let mut roles = roles
+        .into_iter()
+        .filter(|r| role_arn.is_none() || role_arn == Some(&r.role_arn))
+        .filter(|r| principal_arn.is_none() || principal_arn == Some(&r.principal_arn))
+        .collect::<Vec<_>>();
+
+    match roles.len() {
+        0 => Err(Error::SamlRoleNotFound),
+        1 => Ok(roles.remove(0)),
+        _ => {
+            let chosen = chooser(&roles)?;
+            if chosen < roles.len() {
+                Ok(roles.remove(chosen))
+            } else {
+                Err(Error::SamlRoleNotChosen)
+            }
+        }
+    }
+}
+
+/// Asks which role to assume on the controlling terminal, leaving stdin and stdout to the
+/// command.
+pub fn collect(roles: &[Role]) -> Result<usize, Error> {
+    let mut terminal = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(TERMINAL)
+        .map_err(|_| Error::SamlRoleNotChosen)?;
+
+    let mut prompt = String::from("The SAML assertion grants several roles:\n");
+    for (i, role) in roles.iter().enumerate() {
+        prompt.push_str(&format!(
+            "  {}) {} ({})\n",
+            i + 1,
+            role.role_arn,
+            role.principal_arn
+        ));
+    }
+    prompt.push_str(&format!("Please choose a role [1-{}]: ", roles.len()));
+    terminal
+        .write_all(prompt.as_bytes())
+        .map_err(|_| Error::SamlRoleNotChosen)?;
+
+    let mut input = String::new();
+    BufReader::new(terminal)
+        .read_line(&mut input)
+        .map_err(|_| Error::SamlRoleNotChosen)?;
+
+    match input.trim().parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n - 1),
+        _ => Err(Error::SamlRoleNotChosen),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::error::Error;
+    use awsudo::profile::{Profile, SamlAssertion};
+    use awsudo::saml;
+    use awsudo::saml::Role;
+    use std::path::PathBuf;
+
+    fn fixtures_path(file: &str) -> String {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures/saml/");
+        p.push(file);
+        p.to_string_lossy().into_owned()
+    }
+
+    fn role(name: &str) -> Role {
+        Role {
+            role_arn: format!("arn:aws:iam::123456789012:role/{}", name),
+            principal_arn: "arn:aws:iam::123456789012:saml-provider/idp".to_string(),
+        }
+    }
+
+    fn second(_: &[Role]) -> Result<usize, Error> {
+        Ok(1)
+    }
+
+    fn unreachable(_: &[Role]) -> Result<usize, Error> {
+        panic!("no role should need to be chosen")
+    }
+
+    #[test]
+    fn it_reads_the_assertion_from_a_file_or_command() {
+        let from_file = saml::read(&SamlAssertion::File(fixtures_path("assertion"))).unwrap();
+
+        assert!(!from_file.contains('\n'));
+        assert_eq!(
+            saml::read(&SamlAssertion::Command(format!(
+                "cat {}",
+                fixtures_path("assertion")
+            ))),
+            Ok(from_file)
+        );
+        assert_eq!(
+            saml::read(&SamlAssertion::Command("exit 1".to_string())),
+            Err(Error::SamlAssertionNotRead)
+        );
+        assert_eq!(
+            saml::read(&SamlAssertion::File(fixtures_path("unexistent"))),
+            Err(Error::SamlAssertionNotRead)
+        );
+    }
+
+    #[test]
+    fn it_refuses_stdin_when_the_command_takes_it() {
+        let profiles = |assertion: SamlAssertion| {
+            vec![Profile {
+                saml_assertion: Some(assertion),
+                ..Default::default()
+            }]
+        };
+
+        assert_eq!(
+            saml::check_stdin(&profiles(SamlAssertion::Stdin), true),
+            Err(Error::SamlAssertionStdinTaken)
+        );
+        assert_eq!(
+            saml::check_stdin(&profiles(SamlAssertion::Stdin), false),
+            Ok(())
+        );
+        assert_eq!(
+            saml::check_stdin(&profiles(SamlAssertion::File("-".to_string())), true),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn it_lists_the_roles_of_the_assertion() {
+        let encoded = saml::read(&SamlAssertion::File(fixtures_path("assertion"))).unwrap();
+
+        assert_eq!(
+            saml::roles(&encoded),
+            Ok(vec![role("Admin"), role("ReadOnly")])
+        );
+        assert_eq!(
+            saml::roles("bm90IHhtbA=="),
+            Err(Error::SamlAssertionInvalid)
+        );
+    }
+
+    #[test]
+    fn it_chooses_the_role_to_assume() {
+        let roles = vec![role("Admin"), role("ReadOnly")];
+        let admin = role("Admin").role_arn;
+        let other = role("Other").role_arn;
+
+        assert_eq!(
+            saml::choose(roles.clone(), Some(&admin), None, unreachable),
+            Ok(role("Admin"))
+        );
+        assert_eq!(
+            saml::choose(roles.clone(), None, None, second),
+            Ok(role("ReadOnly"))
+        );
+        assert_eq!(
+            saml::choose(vec![role("Admin")], None, None, unreachable),
+            Ok(role("Admin"))
+        );
+        assert_eq!(
+            saml::choose(roles, Some(&other), None, unreachable),
+            Err(Error::SamlRoleNotFound)
+        );
+    }
+}
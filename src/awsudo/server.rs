@@ -0,0 +1,245 @@
+extern crate chrono;
+extern crate rand;
+extern crate serde_json;
+
+use self::chrono::{Duration, Utc};
+use self::rand::distributions::Alphanumeric;
+use self::rand::Rng;
+use self::serde_json::json;
+
+use awsudo::credentials::Credentials;
+use awsudo::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const SERVER_ADDRESS: &str = "127.0.0.1:0";
+const SERVER_PATH: &str = "/credentials";
+const SERVER_TOKEN_LENGTH: usize = 32;
+
+pub type Refresher = Box<dyn Fn() -> Result<Credentials, Error> + Send>;
+
+/// Loopback server following the ECS container credentials protocol, so SDKs in the command
+/// fetch the credentials on demand instead of reading them once from the environment.
+pub struct Server {
+    pub url: String,
+    pub token: String,
+}
+
+struct State {
+    credentials: Credentials,
+    refresh_window: Duration,
+    refresher: Refresher,
+}
+
+impl State {
+    // Refreshes the credentials when they are about to expire, prompting for MFA only if the
+    // refresher cannot find valid credentials in the cache
+    fn current(&mut self) -> Result<&Credentials, Error> {
+        if self.credentials.expires_at - self.refresh_window <= Utc::now() {
+            self.credentials = (self.refresher)()?;
+        }
+
+        Ok(&self.credentials)
+    }
+}
+
+impl Server {
+    pub fn start(
+        credentials: Credentials,
+        refresh_window: Duration,
+        refresher: Refresher,
+    ) -> Result<Server, Error> {
+        let listener = match TcpListener::bind(SERVER_ADDRESS) {
+            Ok(listener) => listener,
+            Err(e) => return Err(Error::ServerNotStarted(e.to_string())),
+        };
+        let port = match listener.local_addr() {
+            Ok(address) => address.port(),
+            Err(e) => return Err(Error::ServerNotStarted(e.to_string())),
+        };
+        let token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(SERVER_TOKEN_LENGTH)
+            .collect::<String>();
+
+        let state = Arc::new(Mutex::new(State {
+            credentials,
+            refresh_window,
+            refresher,
+        }));
+        let expected = token.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = Arc::clone(&state);
+                let expected = expected.clone();
+
+                thread::spawn(move || handle(stream, &expected, &state));
+            }
+        });
+
+        Ok(Server {
+            url: format!("http://127.0.0.1:{}{}", port, SERVER_PATH),
+            token,
+        })
+    }
+}
+
+fn handle(mut stream: TcpStream, token: &str, state: &Mutex<State>) {
+    let (status, body) = match read_authorization(&stream) {
+        Some(ref authorization) if authorization == token => match state.lock() {
+            Ok(mut state) => match state.current() {
+                Ok(c) => ("200 OK", credentials_body(c)),
+                Err(e) => {
+                    eprintln!("awsudo: {}", e);
+                    ("500 Internal Server Error", error_body(&e.to_string()))
+                }
+            },
+            Err(_) => (
+                "500 Internal Server Error",
+                error_body("Credentials are not available"),
+            ),
+        },
+        _ => ("403 Forbidden", error_body("Invalid authorization token")),
+    };
+
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
+
+fn read_authorization(stream: &TcpStream) -> Option<String> {
+    let mut reader = BufReader::new(stream);
+    let mut authorization = None;
+
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {
+                let line = line.trim_end();
+                if line.is_empty() {
+                    return authorization;
+                }
+
+                let mut parts = line.splitn(2, ':');
+                if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                    if name.eq_ignore_ascii_case("authorization") {
+                        authorization = Some(value.trim().to_string());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn credentials_body(credentials: &Credentials) -> String {
+    json!({
+        "AccessKeyId": credentials.access_key_id,
+        "SecretAccessKey": credentials.secret_access_key,
+        "Token": credentials.session_token,
+        "Expiration": credentials.expires_at.to_rfc3339(),
+    })
+    .to_string()
+}
+
+fn error_body(message: &str) -> String {
+    json!({ "message": message }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::credentials::Credentials;
+    use awsudo::error::Error;
+    use awsudo::server::chrono::{Duration, Utc};
+    use awsudo::server::serde_json::{self, Value};
+    use awsudo::server::Server;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    fn credentials(key: &str, expires_in: Duration) -> Credentials {
+        Credentials {
+            access_key_id: key.to_string(),
+            secret_access_key: "M07".to_string(),
+            session_token: "B03".to_string(),
+            expires_at: Utc::now() + expires_in,
+            cached: false,
+            cacheable: true,
+        }
+    }
+
+    fn get(server: &Server, token: &str) -> (String, Value) {
+        let address = server
+            .url
+            .trim_start_matches("http://")
+            .split('/')
+            .next()
+            .unwrap();
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "GET /credentials HTTP/1.1\r\nHost: {}\r\nAuthorization: {}\r\n\r\n",
+            address, token
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let mut parts = response.splitn(2, "\r\n\r\n");
+        let head = parts.next().unwrap().to_string();
+
+        (head, serde_json::from_str(parts.next().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn it_serves_the_credentials_to_authorized_requests() {
+        let server = Server::start(
+            credentials("A23", Duration::hours(1)),
+            Duration::minutes(5),
+            Box::new(|| Err(Error::CacheExpired)),
+        )
+        .unwrap();
+
+        let (head, body) = get(&server, &server.token);
+
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(body["AccessKeyId"], "A23");
+        assert_eq!(body["SecretAccessKey"], "M07");
+        assert_eq!(body["Token"], "B03");
+    }
+
+    #[test]
+    fn it_rejects_requests_with_an_invalid_token() {
+        let server = Server::start(
+            credentials("A23", Duration::hours(1)),
+            Duration::minutes(5),
+            Box::new(|| Err(Error::CacheExpired)),
+        )
+        .unwrap();
+
+        let (head, body) = get(&server, "nope");
+
+        assert!(head.starts_with("HTTP/1.1 403 Forbidden"));
+        assert_eq!(body.get("AccessKeyId"), None);
+    }
+
+    #[test]
+    fn it_refreshes_the_credentials_before_they_expire() {
+        let server = Server::start(
+            credentials("A23", Duration::minutes(2)),
+            Duration::minutes(5),
+            Box::new(|| Ok(credentials("R42", Duration::hours(1)))),
+        )
+        .unwrap();
+
+        let (_, body) = get(&server, &server.token);
+
+        assert_eq!(body["AccessKeyId"], "R42");
+    }
+}
@@ -0,0 +1,67 @@
+extern crate chrono;
+
+use self::chrono::{DateTime, Utc};
+
+use awsudo::error::Error;
+
+/// Interactive shell started when awsudo is given no command, like `sudo -i`.
+pub struct Session {
+    pub profile: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Session {
+    pub fn new(profile: String, expires_at: DateTime<Utc>) -> Session {
+        Session {
+            profile,
+            expires_at,
+        }
+    }
+
+    /// Short label of the session to show in the shell prompt.
+    pub fn prompt(&self) -> String {
+        format!("aws:{}", self.profile)
+    }
+}
+
+/// Refuses to start a shell for a profile from within a shell of another profile, since the
+/// prompt would only show one of them, unless nesting is explicitly allowed.
+pub fn check_nesting(current: Option<String>, profile: &str, nest: bool) -> Result<(), Error> {
+    match current {
+        Some(ref current) if current != profile && !nest => {
+            Err(Error::ShellNested(current.to_owned()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::error::Error;
+    use awsudo::session;
+
+    #[test]
+    fn it_allows_a_shell_outside_of_awsudo() {
+        assert_eq!(session::check_nesting(None, "prod", false), Ok(()));
+    }
+
+    #[test]
+    fn it_allows_a_shell_for_the_same_profile() {
+        assert_eq!(
+            session::check_nesting(Some("prod".to_string()), "prod", false),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn it_refuses_a_shell_for_another_profile() {
+        assert_eq!(
+            session::check_nesting(Some("staging".to_string()), "prod", false),
+            Err(Error::ShellNested("staging".to_string()))
+        );
+        assert_eq!(
+            session::check_nesting(Some("staging".to_string()), "prod", true),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,554 @@
+extern crate chrono;
+extern crate hex;
+extern crate openssl;
+extern crate rusoto_core;
+extern crate serde_json;
+
+use self::chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use self::openssl::sha;
+use self::rusoto_core::request::BufferedHttpResponse;
+use self::rusoto_core::signature::SignedRequest;
+use self::rusoto_core::Region;
+use self::serde_json::{json, Map, Value};
+
+use awsudo::backend::{Backend, Plaintext};
+use awsudo::credentials::Credentials;
+use awsudo::error::Error;
+use awsudo::profile::SsoSession;
+use awsudo::request;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const AWS_SSO_CACHE_PATH: &str = ".aws/sso/cache";
+const AWS_SSO_CLIENT_NAME: &str = "awsudo";
+const AWS_SSO_DEVICE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const AWS_SSO_TOKEN_HEADER: &str = "x-amz-sso_bearer_token";
+const AWS_SSO_POLL_INTERVAL: u64 = 5;
+const AWS_SSO_SLOW_DOWN_SECONDS: u64 = 5;
+
+/// Custom endpoints of the IAM Identity Center services, e.g.: a local stand-in, instead of
+/// the ones of the region of the session.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Endpoints {
+    pub oidc: Option<String>,
+    pub portal: Option<String>,
+}
+
+/// The access token of a session, cached in the format of the AWS CLI so either one can sign
+/// in for the other, along with the client registered to request it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    pub start_url: String,
+    pub region: String,
+    pub access_token: String,
+    pub expires_at: DateTime<Utc>,
+    pub client: Option<Client>,
+    pub refresh_token: Option<String>,
+}
+
+/// A client registered with the OIDC service, reused until its registration expires.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Client {
+    pub id: String,
+    pub secret: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Signs in to the session through the OIDC device authorization flow, asking the user to
+/// confirm the code in a browser, and caches the access token.
+pub fn login(session: &SsoSession, endpoints: &Endpoints) -> Result<Token, Error> {
+    let dir = cache_dir()?;
+
+    let client = match load(&dir, session).ok().and_then(|token| token.client) {
+        Some(ref client) if client.expires_at > Utc::now() => client.clone(),
+        _ => register(session, endpoints)?,
+    };
+
+    let authorization = oidc(
+        session,
+        endpoints,
+        "/device_authorization",
+        &json!({
+            "clientId": client.id,
+            "clientSecret": client.secret,
+            "startUrl": session.start_url,
+        }),
+    )?;
+    let (device_code, user_code, verification_uri, expires_in) = match (
+        string(&authorization, "deviceCode"),
+        string(&authorization, "userCode"),
+        string(&authorization, "verificationUriComplete")
+            .or_else(|| string(&authorization, "verificationUri")),
+        authorization["expiresIn"].as_u64(),
+    ) {
+        (Some(device), Some(user), Some(uri), Some(expires_in)) => (device, user, uri, expires_in),
+        _ => return Err(Error::SsoInvalidResponse),
+    };
+    eprintln!(
+        "awsudo: to sign in to {}, open {} and confirm the code {}",
+        session.start_url, verification_uri, user_code
+    );
+
+    // The token is only issued once the user confirms, until then the service asks to wait
+    let deadline = Instant::now() + Duration::from_secs(expires_in);
+    let mut interval = authorization["interval"]
+        .as_u64()
+        .unwrap_or(AWS_SSO_POLL_INTERVAL);
+    let created = loop {
+        if Instant::now() >= deadline {
+            return Err(Error::SsoLoginExpired);
+        }
+        thread::sleep(Duration::from_secs(interval));
+
+        let result = oidc(
+            session,
+            endpoints,
+            "/token",
+            &json!({
+                "clientId": client.id,
+                "clientSecret": client.secret,
+                "grantType": AWS_SSO_DEVICE_GRANT,
+                "deviceCode": device_code,
+            }),
+        );
+        match result {
+            Err(Error::Sso { ref code, .. }) if code == "authorization_pending" => {}
+            Err(Error::Sso { ref code, .. }) if code == "slow_down" => {
+                interval += AWS_SSO_SLOW_DOWN_SECONDS
+            }
+            Err(Error::Sso { ref code, .. }) if code == "expired_token" => {
+                return Err(Error::SsoLoginExpired)
+            }
+            result => break result?,
+        }
+    };
+
+    let token = match (
+        string(&created, "accessToken"),
+        created["expiresIn"].as_i64(),
+    ) {
+        (Some(access_token), Some(expires_in)) => Token {
+            start_url: session.start_url.clone(),
+            region: session.region.clone(),
+            access_token,
+            expires_at: Utc::now() + chrono::Duration::seconds(expires_in),
+            client: Some(client),
+            refresh_token: string(&created, "refreshToken"),
+        },
+        _ => return Err(Error::SsoInvalidResponse),
+    };
+    store(&dir, session, &token)?;
+
+    Ok(token)
+}
+
+/// Exchanges the cached access token of the session for credentials of a role of an account,
+/// through `GetRoleCredentials`.
+pub fn role_credentials(
+    session: &SsoSession,
+    account_id: &str,
+    role_name: &str,
+    endpoints: &Endpoints,
+) -> Result<Credentials, Error> {
+    let token = load(&cache_dir()?, session)?;
+    if token.expires_at <= Utc::now() {
+        return Err(Error::SsoTokenNotFound(name(session)));
+    }
+
+    let request = credentials_request(session, account_id, role_name, &token, endpoints);
+    match send(request)? {
+        ref response if response.status.is_success() => from_json(response.body_as_str()),
+        ref response if response.status.as_u16() == 401 => {
+            Err(Error::SsoTokenNotFound(name(session)))
+        }
+        response => Err(failure(&response)),
+    }
+}
+
+// The token is the only credential, the request is sent without signing it, so its query
+// string is built here instead of by rusoto
+fn credentials_request(
+    session: &SsoSession,
+    account_id: &str,
+    role_name: &str,
+    token: &Token,
+    endpoints: &Endpoints,
+) -> SignedRequest {
+    let mut request = SignedRequest::new(
+        "GET",
+        "portal.sso",
+        &region("portal.sso", &session.region, endpoints.portal.as_ref()),
+        "/federation/credentials",
+    );
+    request.canonical_query_string = request::form_encode(&[
+        ("account_id", account_id.to_owned()),
+        ("role_name", role_name.to_owned()),
+    ]);
+    request.add_header(AWS_SSO_TOKEN_HEADER, &token.access_token);
+
+    request
+}
+
+/// Name of the cached token of the session, the SHA-1 of its name, or of its start URL for
+/// the legacy settings, as the AWS CLI names it.
+pub fn cache_file(session: &SsoSession) -> String {
+    let key = match session.name {
+        Some(ref name) => name,
+        None => &session.start_url,
+    };
+
+    format!("{}.json", hex::encode(sha::sha1(key.as_bytes())))
+}
+
+/// Reads the cached token of the session.
+pub fn load(dir: &Path, session: &SsoSession) -> Result<Token, Error> {
+    // Written by the AWS CLI too, so it is read as is instead of through a cache backend
+    fs::read_to_string(dir.join(cache_file(session)))
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+        .and_then(|document| Token::from_json(&document))
+        .ok_or_else(|| Error::SsoTokenNotFound(name(session)))
+}
+
+/// Caches the token of the session, only readable by the current user.
+pub fn store(dir: &Path, session: &SsoSession, token: &Token) -> Result<(), Error> {
+    Plaintext.write(dir, &cache_file(session), token.to_json().as_bytes())
+}
+
+impl Token {
+    fn from_json(document: &Value) -> Option<Token> {
+        let client = match (
+            string(document, "clientId"),
+            string(document, "clientSecret"),
+            string(document, "registrationExpiresAt").and_then(|d| date(&d)),
+        ) {
+            (Some(id), Some(secret), Some(expires_at)) => Some(Client {
+                id,
+                secret,
+                expires_at,
+            }),
+            _ => None,
+        };
+
+        Some(Token {
+            start_url: string(document, "startUrl")?,
+            region: string(document, "region")?,
+            access_token: string(document, "accessToken")?,
+            expires_at: date(&string(document, "expiresAt")?)?,
+            client,
+            refresh_token: string(document, "refreshToken"),
+        })
+    }
+
+    fn to_json(&self) -> String {
+        let mut document = Map::new();
+        document.insert("startUrl".to_string(), json!(self.start_url));
+        document.insert("region".to_string(), json!(self.region));
+        document.insert("accessToken".to_string(), json!(self.access_token));
+        document.insert(
+            "expiresAt".to_string(),
+            json!(self.expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        );
+        if let Some(ref client) = self.client {
+            document.insert("clientId".to_string(), json!(client.id));
+            document.insert("clientSecret".to_string(), json!(client.secret));
+            document.insert(
+                "registrationExpiresAt".to_string(),
+                json!(client.expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)),
+            );
+        }
+        if let Some(ref refresh_token) = self.refresh_token {
+            document.insert("refreshToken".to_string(), json!(refresh_token));
+        }
+
+        Value::Object(document).to_string()
+    }
+}
+
+fn register(session: &SsoSession, endpoints: &Endpoints) -> Result<Client, Error> {
+    let mut params = json!({
+        "clientName": AWS_SSO_CLIENT_NAME,
+        "clientType": "public",
+    });
+    if !session.registration_scopes.is_empty() {
+        params["scopes"] = json!(session.registration_scopes);
+    }
+
+    let registered = oidc(session, endpoints, "/client/register", &params)?;
+    match (
+        string(&registered, "clientId"),
+        string(&registered, "clientSecret"),
+        registered["clientSecretExpiresAt"].as_i64(),
+    ) {
+        (Some(id), Some(secret), Some(expires_at)) => Ok(Client {
+            id,
+            secret,
+            expires_at: Utc.timestamp(expires_at, 0),
+        }),
+        _ => Err(Error::SsoInvalidResponse),
+    }
+}
+
+// The OIDC calls take no credentials either, only the client they were registered for
+fn oidc(
+    session: &SsoSession,
+    endpoints: &Endpoints,
+    path: &str,
+    params: &Value,
+) -> Result<Value, Error> {
+    let mut request = SignedRequest::new(
+        "POST",
+        "oidc",
+        &region("oidc", &session.region, endpoints.oidc.as_ref()),
+        path,
+    );
+    request.set_content_type("application/json".to_owned());
+    request.set_payload(Some(params.to_string().into_bytes()));
+
+    match send(request)? {
+        ref response if response.status.is_success() => {
+            serde_json::from_str(response.body_as_str()).map_err(|_| Error::SsoInvalidResponse)
+        }
+        response => Err(failure(&response)),
+    }
+}
+
+// Failing to reach the services is an SSO failure too, not one of STS
+fn send(request: SignedRequest) -> Result<BufferedHttpResponse, Error> {
+    request::send(request, |code, message| Error::Sso { code, message })
+}
+
+// Services of the region of the session, as rusoto builds their hostname from the service
+fn region(service: &str, name: &str, endpoint: Option<&String>) -> Region {
+    match (endpoint, name.parse::<Region>()) {
+        (Some(url), _) => Region::Custom {
+            name: name.to_owned(),
+            endpoint: url.to_owned(),
+        },
+        (None, Ok(region)) => region,
+        (None, Err(_)) => Region::Custom {
+            name: name.to_owned(),
+            endpoint: format!("https://{}.{}.amazonaws.com", service, name),
+        },
+    }
+}
+
+// Reads the credentials of the response of GetRoleCredentials, which expire at a timestamp in
+// milliseconds
+fn from_json(body: &str) -> Result<Credentials, Error> {
+    let document: Value = serde_json::from_str(body).map_err(|_| Error::SsoInvalidResponse)?;
+    let credentials = &document["roleCredentials"];
+
+    match (
+        string(credentials, "accessKeyId"),
+        string(credentials, "secretAccessKey"),
+        string(credentials, "sessionToken"),
+        credentials["expiration"].as_i64(),
+    ) {
+        (Some(access_key_id), Some(secret_access_key), Some(session_token), Some(expiration)) => {
+            Ok(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token,
+                expires_at: Utc
+                    .timestamp(expiration / 1000, (expiration % 1000) as u32 * 1_000_000),
+                cached: false,
+                cacheable: true,
+            })
+        }
+        _ => Err(Error::SsoInvalidResponse),
+    }
+}
+
+// The OIDC service names its errors in `error`, the portal in the x-amzn-ErrorType header
+fn failure(response: &BufferedHttpResponse) -> Error {
+    let document = serde_json::from_str::<Value>(response.body_as_str()).unwrap_or_default();
+
+    Error::Sso {
+        code: string(&document, "error")
+            .or_else(|| {
+                response
+                    .headers
+                    .get("x-amzn-ErrorType")
+                    .map(|t| t.split(':').next().unwrap_or_default().to_string())
+            })
+            .unwrap_or_else(|| response.status.to_string()),
+        message: string(&document, "error_description")
+            .or_else(|| string(&document, "message"))
+            .unwrap_or_else(|| response.body_as_str().to_string()),
+    }
+}
+
+// Shared with the AWS CLI, never relative to the current dir
+fn cache_dir() -> Result<PathBuf, Error> {
+    dirs::home_dir()
+        .map(|home| home.join(AWS_SSO_CACHE_PATH))
+        .ok_or(Error::SsoCacheDirNotFound)
+}
+
+fn name(session: &SsoSession) -> String {
+    session
+        .name
+        .clone()
+        .unwrap_or_else(|| session.start_url.clone())
+}
+
+fn string(document: &Value, key: &str) -> Option<String> {
+    document[key].as_str().map(String::from)
+}
+
+// The AWS CLI v1 wrote dates as 2019-01-01T00:00:00UTC
+fn date(raw: &str) -> Option<DateTime<Utc>> {
+    raw.replace("UTC", "Z").parse::<DateTime<Utc>>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::error::Error;
+    use awsudo::profile::SsoSession;
+    use awsudo::sso;
+    use awsudo::sso::chrono::{TimeZone, Utc};
+    use awsudo::sso::rusoto_core::signature::SignedRequest;
+    use awsudo::sso::{Client, Token};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn fixtures_path(dir: &str) -> PathBuf {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures/sso/");
+        p.push(dir);
+        p
+    }
+
+    fn fixtures_tmp_path(name: &str) -> PathBuf {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("test/fixtures/tmp");
+        p.push(format!("sso_{}", name));
+        p
+    }
+
+    fn session(name: Option<&str>) -> SsoSession {
+        SsoSession {
+            name: name.map(String::from),
+            start_url: "https://example.awsapps.com/start".to_string(),
+            region: "eu-west-1".to_string(),
+            registration_scopes: vec!["sso:account:access".to_string()],
+        }
+    }
+
+    #[test]
+    fn it_names_the_cached_token_as_the_aws_cli() {
+        assert_eq!(
+            sso::cache_file(&session(Some("my-sso"))),
+            "0ad374308c5a4e22f723adf10145eafad7c4031c.json"
+        );
+        assert_ne!(
+            sso::cache_file(&session(None)),
+            sso::cache_file(&session(Some("my-sso")))
+        );
+    }
+
+    #[test]
+    fn it_reads_the_token_cached_by_the_aws_cli() {
+        let token = sso::load(&fixtures_path("cache"), &session(Some("my-sso"))).unwrap();
+
+        assert_eq!(token.access_token, "aoa-token");
+        assert_eq!(token.expires_at, Utc.ymd(2031, 2, 3).and_hms(4, 5, 6));
+        assert_eq!(token.client.map(|c| c.id), Some(String::from("client-id")));
+        assert_eq!(
+            sso::load(&fixtures_path("cache"), &session(Some("unknown"))),
+            Err(Error::SsoTokenNotFound(String::from("unknown")))
+        );
+    }
+
+    #[test]
+    fn it_caches_the_token() {
+        let dir = fixtures_tmp_path("store");
+        let _ = fs::remove_dir_all(&dir);
+        let token = Token {
+            start_url: "https://example.awsapps.com/start".to_string(),
+            region: "eu-west-1".to_string(),
+            access_token: "aoa-token".to_string(),
+            expires_at: Utc.ymd(2031, 2, 3).and_hms(4, 5, 6),
+            client: Some(Client {
+                id: "client-id".to_string(),
+                secret: "client-secret".to_string(),
+                expires_at: Utc.ymd(2031, 4, 5).and_hms(6, 7, 8),
+            }),
+            refresh_token: None,
+        };
+
+        sso::store(&dir, &session(None), &token).unwrap();
+
+        assert_eq!(sso::load(&dir, &session(None)), Ok(token));
+        assert!(
+            fs::read_to_string(dir.join(sso::cache_file(&session(None))))
+                .unwrap()
+                .contains("\"expiresAt\":\"2031-02-03T04:05:06Z\"")
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_reads_the_role_credentials_of_the_response() {
+        let credentials = sso::from_json(
+            "{\"roleCredentials\":{\"accessKeyId\":\"ASIA\",\"secretAccessKey\":\"secret\",\
+             \"sessionToken\":\"token\",\"expiration\":1927857906000}}",
+        )
+        .unwrap();
+
+        assert_eq!(credentials.access_key_id, "ASIA");
+        assert_eq!(credentials.session_token, "token");
+        assert_eq!(credentials.expires_at, Utc.ymd(2031, 2, 3).and_hms(4, 5, 6));
+        assert_eq!(
+            sso::from_json("{\"roleCredentials\":{}}").err(),
+            Some(Error::SsoInvalidResponse)
+        );
+    }
+
+    #[test]
+    fn it_requests_the_role_credentials_with_the_token_only() {
+        let token = sso::load(&fixtures_path("cache"), &session(Some("my-sso"))).unwrap();
+        let request = sso::credentials_request(
+            &session(Some("my-sso")),
+            "123456789012",
+            "Admin Role",
+            &token,
+            &Default::default(),
+        );
+
+        assert_eq!(
+            request.canonical_query_string(),
+            "account_id=123456789012&role_name=Admin%20Role"
+        );
+        assert_eq!(
+            request.headers().get("x-amz-sso_bearer_token"),
+            Some(&vec![b"aoa-token".to_vec()])
+        );
+        assert!(request.headers().get("authorization").is_none());
+    }
+
+    #[test]
+    fn it_uses_the_endpoints_of_the_region_unless_given() {
+        let hostname = |service: &str, endpoint: Option<&String>| {
+            SignedRequest::new(
+                "GET",
+                service,
+                &sso::region(service, "eu-west-1", endpoint),
+                "/",
+            )
+            .hostname()
+        };
+        let local = String::from("http://localhost:8080");
+
+        assert_eq!(hostname("oidc", None), "oidc.eu-west-1.amazonaws.com");
+        assert_eq!(
+            hostname("portal.sso", None),
+            "portal.sso.eu-west-1.amazonaws.com"
+        );
+        assert_eq!(hostname("portal.sso", Some(&local)), "localhost:8080");
+    }
+}
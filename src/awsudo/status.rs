@@ -0,0 +1,69 @@
+use std::process::ExitStatus;
+
+/// The profile could not be loaded from the config file
+pub const PROFILE_FAILURE: i32 = 65;
+/// The request to STS failed
+pub const STS_FAILURE: i32 = 66;
+/// The MFA token was not provided or was rejected
+pub const MFA_FAILURE: i32 = 67;
+/// The credentials cache could not be written or removed
+pub const CACHE_FAILURE: i32 = 68;
+/// The credentials server could not be started
+pub const SERVER_FAILURE: i32 = 69;
+/// An awsudo shell for another profile is already running
+pub const SHELL_NESTED: i32 = 70;
+/// The SAML assertion could not be read or grants no role to assume
+pub const SAML_FAILURE: i32 = 71;
+/// The SSO login failed or its token is missing or expired
+pub const SSO_FAILURE: i32 = 72;
+/// The credential_process of the profile failed or printed invalid credentials
+pub const CREDENTIAL_PROCESS_FAILURE: i32 = 73;
+/// The command was found but could not be executed
+pub const COMMAND_NOT_EXECUTABLE: i32 = 126;
+/// The command could not be started
+pub const COMMAND_FAILURE: i32 = 127;
+
+const SIGNAL_OFFSET: i32 = 128;
+
+/// Maps the status of a finished command to the code awsudo exits with,
+/// following the shell convention of 128+N for commands killed by signal N.
+pub fn code(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => signal(status).map_or(COMMAND_FAILURE, |s| SIGNAL_OFFSET + s),
+    }
+}
+
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use awsudo::status;
+    use std::process::Command;
+
+    fn run(command: &str) -> i32 {
+        status::code(Command::new("sh").arg("-c").arg(command).status().unwrap())
+    }
+
+    #[test]
+    fn it_returns_the_exit_code_of_the_command() {
+        assert_eq!(run("true"), 0);
+        assert_eq!(run("exit 3"), 3);
+    }
+
+    #[test]
+    fn it_returns_128_plus_the_signal_when_the_command_is_killed() {
+        assert_eq!(run("kill -TERM $$"), 143);
+        assert_eq!(run("kill -KILL $$"), 137);
+    }
+}
@@ -1,59 +1,290 @@
 mod awsudo;
 
+use awsudo::backend;
+use awsudo::backend::{Backend, Encrypted, Kind, Memory, Plaintext};
+use awsudo::cache;
 use awsudo::cache::Cache;
 use awsudo::cli;
+use awsudo::cli::{CacheCommand, CLI};
+use awsudo::credentials::Credentials;
 use awsudo::dispatcher;
+use awsudo::environment;
+use awsudo::error::Error;
+use awsudo::export;
 use awsudo::fetcher::Fetcher;
+use awsudo::mfa;
 use awsudo::profile::Profile;
+use awsudo::region;
 use awsudo::request::Request;
+use awsudo::saml;
+use awsudo::server::Server;
+use awsudo::session;
+use awsudo::session::Session;
+use awsudo::sso;
 
-use std::io;
+use std::env;
+use std::process;
+use std::time::Duration;
 
-pub fn token_collector(mfa_serial: String) -> Option<String> {
-    let mut buffer = String::new();
-    println!("Please type your MFA token for {}: ", mfa_serial);
-    io::stdin()
-        .read_line(&mut buffer)
-        .expect("Failed to read your input");
+fn exit_with(error: Error) -> ! {
+    eprintln!("awsudo: {}", error);
+    process::exit(error.exit_code())
+}
+
+fn cache(args: &CLI) -> Cache {
+    cache_named(args, &args.user)
+}
+
+fn cache_named(args: &CLI, name: &str) -> Cache {
+    let backend: Box<dyn Backend> = match args.cache_backend {
+        Kind::Plaintext => Box::new(Plaintext),
+        Kind::Encrypted => Box::new(Encrypted::new(
+            args.cache_passphrase.clone().unwrap_or_default(),
+        )),
+        Kind::Memory => Box::new(Memory),
+    };
+    let cache = Cache::new(args.cache_dir.clone(), name).with_backend(backend);
+
+    match args.expiry_window {
+        Some(seconds) => cache.with_expiry_window(seconds),
+        None => cache,
+    }
+}
+
+// Read once, so the passphrase is not prompted again for every access to the cache
+fn cache_passphrase(args: &CLI) -> Result<Vec<u8>, Error> {
+    match args.cache_key_file {
+        Some(ref path) => backend::read_key_file(path),
+        None => backend::collect_passphrase(),
+    }
+}
+
+fn fetch_credentials(args: &CLI) -> Result<Credentials, Error> {
+    let mut profiles = Profile::with_web_identity(
+        Profile::load_chain(
+            args.config.clone(),
+            args.credentials.clone(),
+            args.user.clone(),
+        ),
+        args.web_identity.clone(),
+    )?;
+    if let Some(requested) = profiles.last_mut() {
+        requested.override_with(args.duration, args.session_name.clone());
+    }
+
+    let region = region::from_config(
+        args.region.clone(),
+        args.sts_endpoint.clone(),
+        &profiles,
+        args.config.clone(),
+    )?;
+
+    // First, try to get credentials from Cache, unless asked to refresh them, as long as they
+    // were requested with the same settings
+    let cache = cache(args)
+        .with_role_arn(profiles.last().and_then(|p| p.role_arn.clone()))
+        .with_fingerprint(cache::fingerprint(&profiles, &region));
+    match cache.fetch() {
+        Ok(credentials) if !args.validate => return Ok(credentials),
+        Err(ref e @ Error::CacheInsecure) | Err(ref e @ Error::CacheNotDecrypted) => {
+            eprintln!("awsudo: {}, ignoring it", e)
+        }
+        _ => {}
+    }
+
+    // Only one process refreshes the session, the others wait and read it from the Cache
+    let _lock = match cache.lock(Duration::from_secs(cache::CACHE_LOCK_TIMEOUT_SECONDS)) {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            eprintln!("awsudo: {}, going on without it", e);
+            None
+        }
+    };
+    if !args.validate {
+        if let Ok(credentials) = cache.fetch() {
+            return Ok(credentials);
+        }
+    }
+
+    // If that doesn't work, it tries then to request a new on from STS, reading a SAML
+    // assertion from stdin only while no command, shell or refresh of the server needs it
+    saml::check_stdin(
+        &profiles,
+        !args.command.is_empty() || args.credentials_server || interactive(args),
+    )?;
+    let request =
+        Request::new(profiles, region, mfa::collect).with_sso_endpoints(args.sso_endpoints.clone());
+    let credentials = if args.mfa_session {
+        // The session of the MFA device is cached on its own, shared by all of its profiles
+        let sessions_args = args.clone();
+        request
+            .with_mfa_sessions(
+                Box::new(move |serial| {
+                    cache_named(&sessions_args, &cache::mfa_session_name(serial))
+                        .with_role_arn(Some(serial.to_owned()))
+                }),
+                args.mfa_session_duration,
+            )
+            .fetch()?
+    } else {
+        request.fetch()?
+    };
+
+    // Persist Credentials on Cache, the command can still run without it
+    if let Err(e) = cache.persist(&credentials) {
+        eprintln!("awsudo: {}, going on without it", e);
+    }
+
+    Ok(credentials)
+}
+
+// Without a command, an interactive shell is started, reading stdin
+fn interactive(args: &CLI) -> bool {
+    args.export.is_none() && !args.credential_process && !args.validate && args.command.is_empty()
+}
 
-    let token: Option<String> = match buffer.trim().parse() {
-        Ok(t) => Some(t),
-        Err(_) => None,
+// Signs in to the SSO session given by name or used by the profile
+fn login(args: &CLI) -> Result<(), Error> {
+    let session = match args.sso_session {
+        Some(ref name) => Profile::load_sso_session(args.config.clone(), name)?,
+        None => Profile::load_from(
+            args.config.clone(),
+            args.credentials.clone(),
+            args.user.clone(),
+        )?
+        .sso_session
+        .ok_or(Error::ProfileSsoNotFound)?,
     };
 
-    token
+    let token = sso::login(&session, &args.sso_endpoints)?;
+    eprintln!(
+        "awsudo: signed in to {} until {}",
+        session.start_url,
+        token.expires_at.to_rfc3339()
+    );
+
+    Ok(())
+}
+
+fn manage_cache(args: &CLI, command: CacheCommand) -> Result<(), Error> {
+    match command {
+        CacheCommand::List => {
+            for entry in cache(args).list()? {
+                println!("{}", entry);
+            }
+        }
+        CacheCommand::Show => println!("{}", cache(args).entry()?),
+        CacheCommand::Clear => {
+            if cache(args).clear()? {
+                println!("{}", args.user);
+            }
+        }
+        CacheCommand::ClearAll => {
+            for profile in cache(args).clear_all()? {
+                println!("{}", profile);
+            }
+        }
+        CacheCommand::Gc => {
+            for profile in cache(args).gc()? {
+                println!("{}", profile);
+            }
+        }
+    }
+
+    Ok(())
 }
 
 fn main() {
     // Parse command arguments
-    let args = cli::parse();
+    let mut args = cli::parse();
 
-    // Get Credentials to be injected
-    // First, try to get credentials from Cache
-    let cache = Cache::new(args.cache_dir, &args.user);
-    let credentials = match cache.fetch() {
-        Ok(credentials) => credentials,
-        Err(_) => {
-            // If that doesn't work, it tries then to request a new on from STS
-            match Profile::load_from(args.config, args.user) {
-                Ok(p) => match Request::new(p, token_collector).fetch() {
-                    Ok(credentials) => credentials,
-                    Err(e) => panic!(e),
-                },
-                Err(e) => panic!(e),
-            }
+    // Only awsudo needs the passphrase, the commands it runs inherit everything else
+    environment::remove_cache_passphrase();
+
+    if let (true, Some(syntax)) = (args.clear, args.export) {
+        // Clearing does not need any Credentials
+        println!("{}", export::clear(syntax));
+        return;
+    }
+
+    if args.login {
+        // Signing in only caches the SSO token, the roles are requested with it afterwards
+        login(&args).unwrap_or_else(|e| exit_with(e));
+        return;
+    }
+
+    if args.cache_backend == Kind::Encrypted && args.cache_passphrase.is_none() {
+        args.cache_passphrase = Some(cache_passphrase(&args).unwrap_or_else(|e| exit_with(e)));
+    }
+
+    if let Some(command) = args.cache_command {
+        manage_cache(&args, command).unwrap_or_else(|e| exit_with(e));
+        return;
+    }
+
+    if args.invalidate {
+        // Like sudo -k, forget the cached session, only going on if there is a command to run
+        cache(&args).clear().unwrap_or_else(|e| exit_with(e));
+        if args.command.is_empty() && args.export.is_none() && !args.validate {
+            return;
         }
-    };
+    }
+
+    // Without a command, start an interactive shell, unless already in one of another profile
+    let interactive = interactive(&args);
+    if interactive {
+        session::check_nesting(
+            env::var(environment::SESSION_PROFILE_VARIABLE).ok(),
+            &args.user,
+            args.nest,
+        )
+        .unwrap_or_else(|e| exit_with(e));
+    }
+
+    // Get Credentials to be injected
+    let credentials = fetch_credentials(&args).unwrap_or_else(|e| exit_with(e));
+
+    if args.validate && args.command.is_empty() {
+        // Like sudo -v, the refreshed session was cached for the next commands
+        return;
+    }
+
+    if args.credential_process {
+        // Hand the Credentials over to the tool calling awsudo as its credential_process
+        println!("{}", credentials.to_credential_process());
+        return;
+    }
+
+    if let Some(syntax) = args.export {
+        // Print the Credentials for the shell to evaluate instead of running a command
+        println!("{}", credentials.export(syntax));
+        return;
+    }
+
+    if interactive {
+        // The shell is only the command once the Credentials were fetched
+        Session::new(args.user.clone(), credentials.expires_at).inject();
+        args.command = vec![dispatcher::user_shell()];
+    }
+
+    if args.credentials_server {
+        // Serve the Credentials to the command, refreshing them through the same path
+        let refresh_args = args.clone();
+        let server = Server::start(
+            credentials,
+            cache(&args).expiry_window,
+            Box::new(move || fetch_credentials(&refresh_args)),
+        )
+        .unwrap_or_else(|e| exit_with(e));
+        server.inject();
+
+        // Run the command while serving it, exiting with its status
+        process::exit(dispatcher::run_as_child(args.command, args.shell));
+    }
 
     // Inject Environment Variables from Credentials
     credentials.inject();
 
-    // Persist Credentials on Cache
-    match cache.persist(credentials) {
-        Err(_) => println!("Failed to cache"),
-        Ok(_) => (),
-    };
-
-    // Run the command with the Environment Credentials
-    dispatcher::run(args.command);
+    // Run the command with the Environment Credentials, exiting with its status
+    process::exit(dispatcher::run(args.command, args.shell));
 }